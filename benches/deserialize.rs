@@ -0,0 +1,195 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use lsif_protocol::reader::deserialize_element;
+use lsif_protocol::reader::interner::Interner;
+
+/// The deserializer as it was before fields were borrowed from the line: the header is
+/// parsed into owned values first, then the label's deserializer parses the whole line
+/// again. Kept
+/// here as the baseline the current deserializer is measured against, for the labels
+/// found in `LINES`.
+mod two_pass {
+    use lsif_protocol::reader::interner::Interner;
+    use lsif_protocol::reader::types::*;
+    use lsif_protocol::types::{EdgeLabel, VertexLabel};
+
+    use lsp_types::{Hover, MonikerKind, Position, UniquenessLevel, Url};
+    use serde::Deserialize;
+    use serde_json::Value;
+
+    fn id(interner: &Interner, value: &Value) -> u64 {
+        match value {
+            Value::String(id) => interner.intern(id.as_bytes()).unwrap(),
+            other => other.as_u64().unwrap(),
+        }
+    }
+
+    pub fn deserialize_element(interner: &Interner, line: &[u8]) -> Element {
+        #[derive(Deserialize)]
+        struct JSONPayload {
+            id: Value,
+            #[serde(rename = "type")]
+            el_type: String,
+            label: String,
+        }
+
+        let payload: JSONPayload = serde_json::from_slice(line).unwrap();
+        let id = id(interner, &payload.id);
+
+        match payload.el_type.as_str() {
+            "vertex" => {
+                let label = VertexLabel::from(payload.label.as_str());
+                Element {
+                    id,
+                    payload: deserialize_vertex(&label, line),
+                    kind: ElementKind::Vertex(label),
+                }
+            }
+            _ => Element {
+                id,
+                kind: ElementKind::Edge(EdgeLabel::from(payload.label.as_str())),
+                payload: Some(deserialize_edge(interner, line)),
+            },
+        }
+    }
+
+    fn deserialize_vertex(label: &VertexLabel, line: &[u8]) -> Option<Payload> {
+        #[derive(Deserialize)]
+        struct DocumentPayload {
+            uri: Url,
+        }
+
+        #[derive(Deserialize)]
+        struct RangePayload {
+            start: Position,
+            end: Position,
+            tag: Option<RangeTag>,
+        }
+
+        #[derive(Deserialize)]
+        struct HoverPayload {
+            result: Hover,
+        }
+
+        #[derive(Deserialize)]
+        struct MonikerPayload {
+            kind: Option<MonikerKind>,
+            scheme: String,
+            identifier: String,
+            unique: Option<UniquenessLevel>,
+        }
+
+        Some(match label {
+            VertexLabel::Document => {
+                let payload: DocumentPayload = serde_json::from_slice(line).unwrap();
+                Payload::Document(payload.uri)
+            }
+            VertexLabel::Range => {
+                let payload: RangePayload = serde_json::from_slice(line).unwrap();
+                Payload::Range(Range {
+                    start_line: payload.start.line,
+                    start_character: payload.start.character,
+                    end_line: payload.end.line,
+                    end_character: payload.end.character,
+                    tag: payload.tag,
+                })
+            }
+            VertexLabel::HoverResult => {
+                let payload: HoverPayload = serde_json::from_slice(line).unwrap();
+                Payload::Hover(payload.result.contents)
+            }
+            VertexLabel::Moniker => {
+                let payload: MonikerPayload = serde_json::from_slice(line).unwrap();
+                Payload::Moniker(Moniker {
+                    kind: payload.kind,
+                    scheme: payload.scheme,
+                    identifier: payload.identifier,
+                    unique: payload.unique,
+                })
+            }
+            VertexLabel::ResultSet => Payload::ResultSet(ResultSet {}),
+            VertexLabel::DefinitionResult => Payload::DefinitionResult,
+            VertexLabel::ReferenceResult => Payload::ReferenceResult,
+            _ => return None,
+        })
+    }
+
+    fn deserialize_edge(interner: &Interner, line: &[u8]) -> Payload {
+        #[derive(Deserialize)]
+        struct EdgePayload {
+            #[serde(rename = "outV")]
+            out_v: Value,
+            #[serde(rename = "inV")]
+            in_v: Option<Value>,
+            #[serde(rename = "inVs")]
+            in_vs: Option<Vec<Value>>,
+            document: Option<Value>,
+            property: Option<ItemProperty>,
+        }
+
+        let payload: EdgePayload = serde_json::from_slice(line).unwrap();
+
+        Payload::Edge(Edge {
+            out_v: id(interner, &payload.out_v),
+            in_v: payload.in_v.map_or(0, |in_v| id(interner, &in_v)),
+            in_vs: payload
+                .in_vs
+                .unwrap_or_default()
+                .iter()
+                .map(|in_v| id(interner, in_v))
+                .collect(),
+            document: payload
+                .document
+                .map_or(0, |document| id(interner, &document)),
+            property: payload.property,
+        })
+    }
+}
+
+/// A slice of a typical TypeScript dump: mostly ranges, result sets and the edges
+/// tying them together, with the occasional hover and moniker.
+const LINES: &[&str] = &[
+    r#"{"id":2,"type":"vertex","label":"document","uri":"file:///project/src/index.ts","languageId":"typescript"}"#,
+    r#"{"id":9,"type":"vertex","label":"resultSet"}"#,
+    r#"{"id":10,"type":"vertex","label":"range","start":{"line":4,"character":9},"end":{"line":4,"character":12},"tag":{"type":"definition","text":"foo","kind":12,"fullRange":{"start":{"line":4,"character":0},"end":{"line":6,"character":1}}}}"#,
+    r#"{"id":11,"type":"edge","label":"next","outV":10,"inV":9}"#,
+    r#"{"id":12,"type":"vertex","label":"hoverResult","result":{"contents":[{"language":"typescript","value":"function foo(): void"},"Does the foo thing."]}}"#,
+    r#"{"id":13,"type":"edge","label":"textDocument/hover","outV":9,"inV":12}"#,
+    r#"{"id":14,"type":"vertex","label":"moniker","kind":"export","scheme":"npm","identifier":"sample:index:foo","unique":"scheme"}"#,
+    r#"{"id":15,"type":"edge","label":"moniker","outV":9,"inV":14}"#,
+    r#"{"id":16,"type":"vertex","label":"definitionResult"}"#,
+    r#"{"id":17,"type":"edge","label":"textDocument/definition","outV":9,"inV":16}"#,
+    r#"{"id":18,"type":"edge","label":"item","outV":16,"inVs":[10],"document":2}"#,
+    r#"{"id":19,"type":"vertex","label":"range","start":{"line":8,"character":2},"end":{"line":8,"character":5},"tag":{"type":"reference","text":"foo"}}"#,
+    r#"{"id":20,"type":"vertex","label":"referenceResult"}"#,
+    r#"{"id":21,"type":"edge","label":"item","outV":20,"inVs":[10],"document":2,"property":"definitions"}"#,
+    r#"{"id":22,"type":"edge","label":"item","outV":20,"inVs":[19],"document":2,"property":"references"}"#,
+    r#"{"id":23,"type":"edge","label":"contains","outV":2,"inVs":[10,19]}"#,
+];
+
+fn deserialize(c: &mut Criterion) {
+    let bytes: usize = LINES.iter().map(|line| line.len()).sum();
+
+    let mut group = c.benchmark_group("deserialize_element");
+    group.throughput(Throughput::Bytes(bytes as u64));
+    group.bench_function("two-pass baseline", |b| {
+        let interner = Interner::new();
+        b.iter(|| {
+            for line in LINES {
+                black_box(two_pass::deserialize_element(&interner, line.as_bytes()));
+            }
+        })
+    });
+    group.bench_function("borrowed fields", |b| {
+        let interner = Interner::new();
+        b.iter(|| {
+            for line in LINES {
+                black_box(deserialize_element(&interner, line.as_bytes()).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, deserialize);
+criterion_main!(benches);
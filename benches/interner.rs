@@ -0,0 +1,103 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use lsif_protocol::reader::interner::{Interner, INTERNED_ID_BASE};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The interner as it was before sharding: one map and the reverse lookup table behind
+/// one lock, kept here as the baseline the sharded interner is measured against.
+#[derive(Clone, Default)]
+struct MutexInterner {
+    ids: Arc<Mutex<Ids>>,
+}
+
+#[derive(Default)]
+struct Ids {
+    map: HashMap<Arc<str>, u64>,
+    raw: Vec<Arc<str>>,
+}
+
+impl MutexInterner {
+    fn intern(&self, raw: &[u8]) -> u64 {
+        let s = std::str::from_utf8(&raw[1..raw.len() - 1]).unwrap();
+        let s = if s.contains('\\') {
+            Cow::Owned(serde_json::from_slice::<String>(raw).unwrap())
+        } else {
+            Cow::Borrowed(s)
+        };
+        if let Ok(num) = s.parse::<u64>() {
+            return num;
+        }
+
+        let mut ids = self.ids.lock().unwrap();
+        if let Some(id) = ids.map.get(s.as_ref()) {
+            return *id;
+        }
+
+        let id = INTERNED_ID_BASE + ids.raw.len() as u64;
+        let s: Arc<str> = s.into();
+        ids.map.insert(s.clone(), id);
+        ids.raw.push(s);
+        id
+    }
+}
+
+/// String ids the way a dump references them: every id is defined once and then used by
+/// a few edges, spread across the file.
+fn ids() -> Vec<Vec<u8>> {
+    let unique: Vec<Vec<u8>> = (0..10_000)
+        .map(|i| format!(r#""{:08x}-4e8f-9a1b-{:012x}""#, i, i * 7919).into_bytes())
+        .collect();
+
+    (0..4).flat_map(|_| unique.iter().cloned()).collect()
+}
+
+fn interner(c: &mut Criterion) {
+    let ids = ids();
+
+    let mut group = c.benchmark_group("interner");
+    group.throughput(Throughput::Elements(ids.len() as u64));
+
+    for threads in [1, 4] {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+
+        group.bench_with_input(BenchmarkId::new("mutex", threads), &ids, |b, ids| {
+            b.iter(|| {
+                let interner = MutexInterner::default();
+                pool.install(|| {
+                    ids.par_chunks(256).for_each(|chunk| {
+                        for id in chunk {
+                            black_box(interner.intern(id));
+                        }
+                    })
+                })
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("sharded", threads), &ids, |b, ids| {
+            b.iter(|| {
+                let interner = Interner::new();
+                pool.install(|| {
+                    ids.par_chunks(256).for_each(|chunk| {
+                        for id in chunk {
+                            black_box(interner.intern(id).unwrap());
+                        }
+                    })
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, interner);
+criterion_main!(benches);
@@ -0,0 +1,351 @@
+use std::io::{self, BufRead, Chain, Cursor, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncBufRead, AsyncRead, AsyncReadExt};
+use futures::ready;
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::io::BufReader;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The number of bytes read to tell the encoding, enough for the longest magic number.
+const SNIFF_LEN: usize = 4;
+
+/// The encodings a dump can be read in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Tells the encoding from the first bytes of a dump. Plain dumps start with `{` or
+    /// whitespace, which no compressed format starts with.
+    pub fn sniff(start: &[u8]) -> Compression {
+        if start.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if start.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// The input of a `Decoder`: the bytes read to tell the encoding, followed by the rest.
+type Sniffed<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// A dump as returned by `decompress`, decoded on the fly if it was compressed.
+pub enum Decoder<R> {
+    Plain(Sniffed<R>),
+    #[cfg(feature = "gzip")]
+    Gzip(BufReader<flate2::bufread::MultiGzDecoder<Sniffed<R>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(BufReader<zstd::stream::read::Decoder<'static, Sniffed<R>>>),
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoder::Plain(r) => r.read(buf),
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(r) => r.read(buf),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(r) => r.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Decoder::Plain(r) => r.fill_buf(),
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(r) => r.fill_buf(),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(r) => r.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            Decoder::Plain(r) => r.consume(amt),
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(r) => r.consume(amt),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(r) => r.consume(amt),
+        }
+    }
+}
+
+/// Wraps `r` in a decoder if it holds a compressed dump, leaving plain dumps as they are.
+/// The encoding is told from the first few bytes, which are read however short the reads
+/// of `r` are. Decoders are only available with the `gzip` and `zstd` features; a dump in
+/// an encoding whose feature is disabled fails to open rather than being read as garbage.
+pub fn decompress<R: BufRead>(mut r: R) -> io::Result<Decoder<R>> {
+    let mut start = Vec::new();
+    r.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut start)?;
+
+    let compression = Compression::sniff(&start);
+    let r = Cursor::new(start).chain(r);
+
+    match compression {
+        Compression::None => Ok(Decoder::Plain(r)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Decoder::Gzip(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(r),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Decoder::Zstd(BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(r)?,
+        ))),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => Err(disabled("gzip")),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(disabled("zstd")),
+    }
+}
+
+/// The input of an `AsyncDecoder` once its encoding is told.
+type AsyncSniffed<R> = futures::io::Chain<futures::io::Cursor<Vec<u8>>, R>;
+
+/// A dump as returned by `decompress_async`, decoded on the fly if it was compressed.
+pub struct AsyncDecoder<R> {
+    state: AsyncState<R>,
+}
+
+enum AsyncState<R> {
+    /// The bytes read so far to tell the encoding, and the rest of the input.
+    Sniffing(Vec<u8>, Option<R>),
+    Plain(AsyncSniffed<R>),
+    #[cfg(feature = "gzip")]
+    Gzip(futures::io::BufReader<async_compression::futures::bufread::GzipDecoder<AsyncSniffed<R>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(futures::io::BufReader<async_compression::futures::bufread::ZstdDecoder<AsyncSniffed<R>>>),
+    /// An encoding whose feature is disabled.
+    #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+    Disabled(&'static str),
+}
+
+/// Like `decompress`, but for async readers. The encoding is told once the decoder is
+/// first polled, so nothing is read up front.
+pub fn decompress_async<R: AsyncBufRead + Unpin>(r: R) -> AsyncDecoder<R> {
+    AsyncDecoder {
+        state: AsyncState::Sniffing(Vec::with_capacity(SNIFF_LEN), Some(r)),
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncDecoder<R> {
+    /// Reads the first few bytes and wraps the input in a decoder for their encoding.
+    fn poll_sniff(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let (start, r) = match &mut self.state {
+            AsyncState::Sniffing(start, r) => (start, r),
+            #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+            AsyncState::Disabled(feature) => return Poll::Ready(Err(disabled(feature))),
+            _ => return Poll::Ready(Ok(())),
+        };
+
+        if let Some(r) = r {
+            while start.len() < SNIFF_LEN {
+                let buf = ready!(Pin::new(&mut *r).poll_fill_buf(cx))?;
+                if buf.is_empty() {
+                    break;
+                }
+                let len = buf.len().min(SNIFF_LEN - start.len());
+                start.extend_from_slice(&buf[..len]);
+                Pin::new(&mut *r).consume(len);
+            }
+        }
+
+        let compression = Compression::sniff(start);
+        let r = futures::io::Cursor::new(std::mem::take(start)).chain(r.take().unwrap());
+
+        self.state = match compression {
+            Compression::None => AsyncState::Plain(r),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut decoder = async_compression::futures::bufread::GzipDecoder::new(r);
+                decoder.multiple_members(true);
+                AsyncState::Gzip(futures::io::BufReader::new(decoder))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut decoder = async_compression::futures::bufread::ZstdDecoder::new(r);
+                decoder.multiple_members(true);
+                AsyncState::Zstd(futures::io::BufReader::new(decoder))
+            }
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => AsyncState::Disabled("gzip"),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => AsyncState::Disabled("zstd"),
+        };
+
+        self.poll_sniff(cx)
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_sniff(cx))?;
+
+        match &mut this.state {
+            AsyncState::Plain(r) => Pin::new(r).poll_read(cx, buf),
+            #[cfg(feature = "gzip")]
+            AsyncState::Gzip(r) => Pin::new(r).poll_read(cx, buf),
+            #[cfg(feature = "zstd")]
+            AsyncState::Zstd(r) => Pin::new(r).poll_read(cx, buf),
+            _ => unreachable!("the encoding is told by poll_sniff"),
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for AsyncDecoder<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_sniff(cx))?;
+
+        match &mut this.state {
+            AsyncState::Plain(r) => Pin::new(r).poll_fill_buf(cx),
+            #[cfg(feature = "gzip")]
+            AsyncState::Gzip(r) => Pin::new(r).poll_fill_buf(cx),
+            #[cfg(feature = "zstd")]
+            AsyncState::Zstd(r) => Pin::new(r).poll_fill_buf(cx),
+            _ => unreachable!("the encoding is told by poll_sniff"),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        match &mut self.get_mut().state {
+            AsyncState::Plain(r) => Pin::new(r).consume(amt),
+            #[cfg(feature = "gzip")]
+            AsyncState::Gzip(r) => Pin::new(r).consume(amt),
+            #[cfg(feature = "zstd")]
+            AsyncState::Zstd(r) => Pin::new(r).consume(amt),
+            // nothing has been handed out to consume
+            _ => {}
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn disabled(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "the dump is {} compressed, which needs the `{}` feature",
+            feature, feature
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::BufReader;
+
+    const DUMP: &str = "{\"id\":1,\"type\":\"vertex\",\"label\":\"resultSet\"}\n";
+
+    /// Hands out a byte per read, like a slow pipe.
+    fn trickle(bytes: impl Into<Vec<u8>>) -> BufReader<Cursor<Vec<u8>>> {
+        BufReader::with_capacity(1, Cursor::new(bytes.into()))
+    }
+
+    fn read(mut r: impl Read) -> String {
+        let mut out = String::new();
+        r.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    /// Reads all of `bytes` through `decompress_async`, a byte per read if `trickle` is set.
+    fn read_async(bytes: impl Into<Vec<u8>>, trickle: bool) -> io::Result<String> {
+        let capacity = if trickle { 1 } else { 1 << 13 };
+        let r =
+            futures::io::BufReader::with_capacity(capacity, futures::io::Cursor::new(bytes.into()));
+
+        let mut out = String::new();
+        futures::executor::block_on(decompress_async(r).read_to_string(&mut out))?;
+        Ok(out)
+    }
+
+    #[test]
+    fn sniff() {
+        assert_eq!(Compression::sniff(DUMP.as_bytes()), Compression::None);
+        assert_eq!(Compression::sniff(b""), Compression::None);
+        assert_eq!(Compression::sniff(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+        assert_eq!(
+            Compression::sniff(&[0x28, 0xb5, 0x2f, 0xfd, 0]),
+            Compression::Zstd
+        );
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(read(decompress(Cursor::new(DUMP)).unwrap()), DUMP);
+        assert_eq!(read(decompress(trickle(DUMP)).unwrap()), DUMP);
+        assert_eq!(read(decompress(trickle("{}")).unwrap()), "{}");
+        assert_eq!(read_async(DUMP, false).unwrap(), DUMP);
+        assert_eq!(read_async(DUMP, true).unwrap(), DUMP);
+        assert_eq!(read_async("{}", true).unwrap(), "{}");
+        assert_eq!(read_async("", false).unwrap(), "");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(DUMP.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(
+            read(decompress(Cursor::new(compressed.clone())).unwrap()),
+            DUMP
+        );
+        assert_eq!(read(decompress(trickle(compressed.clone())).unwrap()), DUMP);
+        assert_eq!(read_async(compressed.clone(), false).unwrap(), DUMP);
+        assert_eq!(read_async(compressed, true).unwrap(), DUMP);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let compressed = zstd::stream::encode_all(DUMP.as_bytes(), 0).unwrap();
+
+        assert_eq!(
+            read(decompress(Cursor::new(compressed.clone())).unwrap()),
+            DUMP
+        );
+        assert_eq!(read(decompress(trickle(compressed.clone())).unwrap()), DUMP);
+        assert_eq!(read_async(compressed.clone(), false).unwrap(), DUMP);
+        assert_eq!(read_async(compressed, true).unwrap(), DUMP);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn disabled() {
+        let zstd = vec![0x28, 0xb5, 0x2f, 0xfd, 0];
+
+        for err in [
+            decompress(Cursor::new(zstd.clone())).err(),
+            decompress(trickle(zstd.clone())).err(),
+            read_async(zstd.clone(), false).err(),
+            read_async(zstd, true).err(),
+        ] {
+            let err = err.expect("expected zstd input to be rejected");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("`zstd` feature"));
+        }
+    }
+}
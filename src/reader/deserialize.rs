@@ -1,224 +1,1413 @@
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
-use serde_json::value::Value;
+use serde_json::value::RawValue;
 
-use lsp_types::{Range as LSRange, Url};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag, DocumentLink, DocumentSymbol,
+    FoldingRange, HoverContents, LanguageString, MarkedString, MarkupContent, MarkupKind,
+    MonikerKind, NumberOrString, Position, Range as LSRange, SymbolKind, UniquenessLevel, Url,
+};
 
 use super::interner::Interner;
 use super::types::*;
 
 use lazy_static::lazy_static;
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
-type Deserializer = fn(&[u8]) -> Result<Payload>;
+/// Major/minor versions of the LSIF protocol that the reader understands.
+const SUPPORTED_VERSIONS: &[(u32, u32)] = &[(0, 4), (0, 5)];
+
+/// Turns the fields of a line into its payload. Deserializers for labels this crate doesn't
+/// know about generally produce a `Payload::Custom`.
+pub type PayloadDeserializer = Arc<dyn Fn(&Interner, &Fields) -> Result<Payload> + Send + Sync>;
+
+type Builtin = fn(&Interner, &Fields) -> Result<Payload>;
 
 lazy_static! {
-    static ref VERTEX_DESERIALIZERS: HashMap<&'static str, Deserializer> = [
-        ("metaData", deserialize_metadata as Deserializer),
-        ("document", deserialize_document as Deserializer),
-        ("range", deserialize_range as Deserializer),
-        ("hoverResult", deserialize_hover as Deserializer),
-        ("moniker", deserialize_moniker as Deserializer),
-        (
-            "packageInformation",
-            deserialize_package_info as Deserializer
-        ),
-        ("diagnosticResult", deserialize_diagnostics as Deserializer)
-    ]
-    .iter()
-    .cloned()
-    .collect();
+    static ref VERTEX_DESERIALIZERS: HashMap<VertexLabel, PayloadDeserializer> = {
+        let builtins: &[(VertexLabel, Builtin)] = &[
+            (VertexLabel::Metadata, deserialize_metadata),
+            (VertexLabel::Document, deserialize_document),
+            (VertexLabel::Range, deserialize_range),
+            (VertexLabel::HoverResult, deserialize_hover),
+            (VertexLabel::Moniker, deserialize_moniker),
+            (VertexLabel::PackageInfo, deserialize_package_info),
+            (VertexLabel::DiagnosticResult, deserialize_diagnostics),
+            (VertexLabel::ResultSet, deserialize_result_set),
+            (VertexLabel::DefinitionResult, deserialize_definition_result),
+            (
+                VertexLabel::DeclarationResult,
+                deserialize_declaration_result,
+            ),
+            (
+                VertexLabel::TypeDefinitionResult,
+                deserialize_type_definition_result,
+            ),
+            (VertexLabel::ReferenceResult, deserialize_reference_result),
+            (
+                VertexLabel::ImplementationResult,
+                deserialize_implementation_result,
+            ),
+            (
+                VertexLabel::DocumentSymbolResult,
+                deserialize_document_symbols,
+            ),
+            (VertexLabel::FoldingRangeResult, deserialize_folding_ranges),
+            (VertexLabel::DocumentLinkResult, deserialize_document_links),
+            (VertexLabel::Project, deserialize_project),
+            (VertexLabel::Event, deserialize_event),
+        ];
+        builtins
+            .iter()
+            .cloned()
+            .map(|(label, func)| (label, Arc::new(func) as PayloadDeserializer))
+            .collect()
+    };
+    static ref EMPTY_REGISTRY: Registry = Registry::new();
 }
 
+/// Deserializes a single line of an LSIF dump. The `id`, `type` and `label` are read up
+/// front and every other field is only scanned for its extent, kept as raw JSON borrowed
+/// from `line`. The label-specific deserializer then parses the fields it uses, so those
+/// are read twice, but the rest of the line isn't parsed into values.
 pub fn deserialize_element(interner: &Interner, line: &[u8]) -> Result<Element> {
-    #[derive(Deserialize, Serialize)]
-    struct JSONPayload {
-        //#[serde(borrow)]
-        id: Value,
-        #[serde(rename = "type")]
-        el_type: String,
-        label: String,
-    }
+    deserialize_element_with(&EMPTY_REGISTRY, interner, line)
+}
 
-    let payload: JSONPayload = serde_json::from_slice(line)?;
+/// Like `deserialize_element`, but consults `registry` before the built-in deserializers.
+pub fn deserialize_element_with(
+    registry: &Registry,
+    interner: &Interner,
+    line: &[u8],
+) -> Result<Element> {
+    let mut raw: RawElement = serde_json::from_slice(line).map_err(|err| json_error(err, 0))?;
+    raw.fields.line = line;
 
-    let id = if payload.id.is_string() {
-        interner.intern(payload.id.as_str().unwrap().as_bytes())?
-    } else {
-        // better be int
-        payload.id.as_u64().unwrap()
-    };
+    let located = |err: ProtocolError| err.in_element(Some(raw.id.get()), &raw.label);
+
+    let id = raw_to_id(interner, raw.id)
+        .map_err(|err| located(err.at_offset(raw.fields.offset(raw.id))))?;
 
-    let element = Element {
-        id,
-        el_type: payload.el_type.clone(),
-        label: payload.label.clone(),
-        payload: if payload.el_type == "edge" {
-            Some(deserialize_edge(interner, line)?)
-        } else if let Some(func) = VERTEX_DESERIALIZERS.get(payload.label.as_str()) {
-            Some(func(line)?)
-        } else {
-            None
-        },
+    let element = match raw.el_type.as_ref() {
+        "vertex" => {
+            let label = VertexLabel::from(raw.label.as_ref());
+            Element {
+                id,
+                payload: match registry
+                    .vertices
+                    .get(&label)
+                    .or_else(|| VERTEX_DESERIALIZERS.get(&label))
+                {
+                    Some(func) => Some(func(interner, &raw.fields).map_err(located)?),
+                    None => None,
+                },
+                kind: ElementKind::Vertex(label),
+            }
+        }
+        "edge" => {
+            let label = EdgeLabel::from(raw.label.as_ref());
+            Element {
+                id,
+                payload: match registry.edges.get(&label) {
+                    Some(func) => Some(func(interner, &raw.fields).map_err(located)?),
+                    None => Some(deserialize_edge(interner, &raw.fields).map_err(located)?),
+                },
+                kind: ElementKind::Edge(label),
+            }
+        }
+        other => {
+            return Err(located(ProtocolError::new(ErrorKind::Other(format!(
+                "unknown element type {}",
+                other
+            )))))
+        }
     };
 
     Ok(element)
 }
 
-fn deserialize_edge(interner: &Interner, line: &[u8]) -> Result<Payload> {
-    #[derive(Deserialize, Serialize)]
-    struct EdgePayload {
-        #[serde(rename = "outV")]
-        out_v: Value,
-        #[serde(rename = "inV")]
-        in_v: Option<Value>,
-        #[serde(rename = "inVs")]
-        in_vs: Option<Vec<Value>>,
-        #[serde(rename = "document")]
-        document: Option<Value>,
+/// Deserializers registered by the caller for specific labels, typically ones emitted by
+/// indexer-specific extensions. They take precedence over the built-in deserializers, which
+/// remain in use for every label without a registration.
+#[derive(Clone, Default)]
+pub struct Registry {
+    vertices: HashMap<VertexLabel, PayloadDeserializer>,
+    edges: HashMap<EdgeLabel, PayloadDeserializer>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
     }
 
-    let payload: EdgePayload = serde_json::from_slice(line)?;
+    pub fn register_vertex<L, F>(&mut self, label: L, deserializer: F) -> &mut Registry
+    where
+        L: Into<VertexLabel>,
+        F: Fn(&Interner, &Fields) -> Result<Payload> + Send + Sync + 'static,
+    {
+        // `VertexLabel::Unknown("range")` has to land on the same entry as `VertexLabel::Range`
+        let label = VertexLabel::from(label.into().as_str());
+        self.vertices.insert(label, Arc::new(deserializer));
+        self
+    }
 
-    let out_v = if payload.out_v.is_string() {
-        interner.intern(payload.out_v.as_str().unwrap().as_bytes())?
-    } else {
-        payload.out_v.as_u64().unwrap()
-    };
+    pub fn register_edge<L, F>(&mut self, label: L, deserializer: F) -> &mut Registry
+    where
+        L: Into<EdgeLabel>,
+        F: Fn(&Interner, &Fields) -> Result<Payload> + Send + Sync + 'static,
+    {
+        let label = EdgeLabel::from(label.into().as_str());
+        self.edges.insert(label, Arc::new(deserializer));
+        self
+    }
+}
 
-    let in_v = if let Some(in_v) = payload.in_v {
-        let in_v = if let Value::String(in_v) = in_v {
-            interner.intern(in_v.as_bytes())?
-        } else {
-            in_v.as_u64().unwrap()
-        };
-        in_v
-    } else {
-        0 as u64
-    };
+/// An LSIF line split into its header and the raw JSON of its remaining fields.
+struct RawElement<'a> {
+    id: &'a RawValue,
+    el_type: Cow<'a, str>,
+    label: Cow<'a, str>,
+    fields: Fields<'a>,
+}
 
-    let document = if let Some(document) = payload.document {
-        let document = if let Value::String(document) = document {
-            interner.intern(document.as_bytes())?
-        } else {
-            document.as_u64().unwrap()
-        };
-        document
-    } else {
-        0 as u64
-    };
+/// The label-specific fields of a line, in input order, i.e. everything but `id`, `type`
+/// and `label`. Lines carry a handful of fields, so a linear scan beats hashing.
+pub struct Fields<'a> {
+    entries: Vec<(Cow<'a, str>, &'a RawValue)>,
+    /// The line the fields were read from, used to locate errors.
+    line: &'a [u8],
+}
 
-    let in_vs = payload.in_vs.map_or_else(
-        || Vec::new(),
-        |in_vs| {
-            in_vs
-                .iter()
-                .map(|v| {
-                    let v = if let Value::String(v) = v {
-                        interner.intern(v.as_bytes()).unwrap()
-                    } else {
-                        v.as_u64().unwrap()
-                    };
-                    v
+impl<'a> Fields<'a> {
+    /// The raw JSON of the named field.
+    pub fn raw(&self, name: &str) -> Option<&'a RawValue> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// Parses the named field if present. `null` is treated like an absent field when `T`
+    /// is an `Option`.
+    pub fn get<T: Deserialize<'a>>(&self, name: &str) -> Result<Option<T>> {
+        match self.raw(name) {
+            Some(value) => match serde_json::from_str(value.get()) {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(err) => {
+                    let mut err = json_error(err, self.offset(value));
+                    if let ErrorKind::JSONParse(message) = &mut err.kind {
+                        *message = format!("field `{}`: {}", name, message);
+                    }
+                    Err(err)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get`, but fails if the field is absent.
+    pub fn require<T: Deserialize<'a>>(&self, name: &str) -> Result<T> {
+        self.get(name)?.ok_or_else(|| missing_field(name))
+    }
+
+    /// Interns the named field, which must hold a vertex or edge id.
+    pub fn id(&self, interner: &Interner, name: &str) -> Result<Option<u64>> {
+        match self.raw(name) {
+            Some(value) => Ok(Some(self.id_of(interner, value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Interns an id found within one of the fields.
+    fn id_of(&self, interner: &Interner, value: &RawValue) -> Result<u64> {
+        raw_to_id(interner, value).map_err(|err| err.at_offset(self.offset(value)))
+    }
+
+    /// The offset of `value` within the line. Every value is borrowed from the line.
+    fn offset(&self, value: &RawValue) -> usize {
+        (value.get().as_ptr() as usize).saturating_sub(self.line.as_ptr() as usize)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawElement<'de> {
+    fn deserialize<D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct RawElementVisitor;
+
+        impl<'de> Visitor<'de> for RawElementVisitor {
+            type Value = RawElement<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an LSIF element")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                let mut id = None;
+                let mut el_type = None;
+                let mut label = None;
+                let mut entries = Vec::new();
+
+                while let Some(CowStr(key)) = map.next_key()? {
+                    match key.as_ref() {
+                        "id" => id = Some(map.next_value()?),
+                        "type" => el_type = Some(map.next_value::<CowStr>()?.0),
+                        "label" => label = Some(map.next_value::<CowStr>()?.0),
+                        _ => entries.push((key, map.next_value()?)),
+                    }
+                }
+
+                Ok(RawElement {
+                    id: id.ok_or_else(|| de::Error::missing_field("id"))?,
+                    el_type: el_type.ok_or_else(|| de::Error::missing_field("type"))?,
+                    label: label.ok_or_else(|| de::Error::missing_field("label"))?,
+                    fields: Fields { entries, line: &[] },
                 })
-                .collect::<Vec<u64>>()
-        },
-    );
+            }
+        }
+
+        deserializer.deserialize_map(RawElementVisitor)
+    }
+}
+
+/// A string borrowed from the input where possible. Only strings containing escape
+/// sequences need to be copied.
+struct CowStr<'a>(Cow<'a, str>);
+
+impl<'de> Deserialize<'de> for CowStr<'de> {
+    fn deserialize<D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct CowStrVisitor;
+
+        impl<'de> Visitor<'de> for CowStrVisitor {
+            type Value = CowStr<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(
+                self,
+                v: &'de str,
+            ) -> std::result::Result<Self::Value, E> {
+                Ok(CowStr(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Self::Value, E> {
+                Ok(CowStr(Cow::Owned(v.to_owned())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<Self::Value, E> {
+                Ok(CowStr(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(CowStrVisitor)
+    }
+}
+
+fn deserialize_edge(interner: &Interner, fields: &Fields) -> Result<Payload> {
+    let in_vs = match fields.get::<Vec<&RawValue>>("inVs")? {
+        Some(in_vs) => in_vs
+            .into_iter()
+            .map(|v| fields.id_of(interner, v))
+            .collect::<Result<Vec<u64>>>()?,
+        None => Vec::new(),
+    };
 
     Ok(Payload::Edge(Edge {
-        out_v,
-        in_v,
+        out_v: fields
+            .id(interner, "outV")?
+            .ok_or_else(|| missing_field("outV"))?,
+        in_v: fields.id(interner, "inV")?.unwrap_or_default(),
         in_vs,
-        document,
+        document: fields.id(interner, "document")?.unwrap_or_default(),
+        property: fields.get("property")?,
     }))
 }
 
-fn deserialize_metadata(line: &[u8]) -> Result<Payload> {
+fn deserialize_metadata(_interner: &Interner, fields: &Fields) -> Result<Payload> {
     #[derive(Deserialize, Serialize)]
-    struct MetaPayload {
-        version: String,
-        #[serde(rename = "projectRoot")]
-        project_root: String,
+    struct ToolInfoPayload {
+        name: String,
+        version: Option<String>,
+        args: Option<Vec<String>>,
     }
 
-    let payload: MetaPayload = serde_json::from_slice(line)?;
+    let version: String = fields.require("version")?;
+
+    check_version(&version)?;
 
     Ok(Payload::MetaData(MetaData {
-        version: payload.version,
-        project_root: payload.project_root,
+        version,
+        project_root: fields.require("projectRoot")?,
+        position_encoding: fields
+            .get("positionEncoding")?
+            .unwrap_or_else(|| "utf-16".into()),
+        tool_info: fields
+            .get::<ToolInfoPayload>("toolInfo")?
+            .map(|info| ToolInfo {
+                name: info.name,
+                version: info.version,
+                args: info.args.unwrap_or_default(),
+            }),
     }))
 }
 
-fn deserialize_document(line: &[u8]) -> Result<Payload> {
-    #[derive(Deserialize, Serialize)]
-    struct DocumentPayload {
-        uri: Url,
-    }
+/// Rejects dumps whose major/minor protocol version falls outside
+/// `SUPPORTED_VERSIONS`. Patch and pre-release components are ignored.
+fn check_version(version: &str) -> Result<()> {
+    let mut parts = version.split(['.', '-']);
 
-    let payload: DocumentPayload = serde_json::from_slice(line)?;
+    let major_minor = match (parts.next(), parts.next()) {
+        (Some(major), Some(minor)) => major.parse::<u32>().ok().zip(minor.parse::<u32>().ok()),
+        _ => None,
+    };
 
-    Ok(Payload::Document(payload.uri))
+    match major_minor {
+        Some(major_minor) if SUPPORTED_VERSIONS.contains(&major_minor) => Ok(()),
+        _ => Err(ProtocolError::new(ErrorKind::UnsupportedVersion(
+            version.into(),
+        ))),
+    }
 }
 
-fn deserialize_range(line: &[u8]) -> Result<Payload> {
-    let payload: LSRange = serde_json::from_slice(line)?;
+fn deserialize_document(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    Ok(Payload::Document(fields.require("uri")?))
+}
+
+fn deserialize_range(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    // RangeTag's own Deserialize impl is internally tagged, which makes serde buffer the
+    // whole tag before picking a variant. Ranges make up a large part of every dump, so
+    // the tag is read flat instead.
+    #[derive(Deserialize, Serialize)]
+    struct TagPayload<'a> {
+        #[serde(rename = "type", borrow)]
+        tag_type: Cow<'a, str>,
+        text: String,
+        kind: Option<SymbolKind>,
+        #[serde(rename = "fullRange")]
+        full_range: Option<LSRange>,
+        deprecated: Option<bool>,
+        detail: Option<String>,
+    }
+
+    let start: Position = fields.require("start")?;
+    let end: Position = fields.require("end")?;
+
+    let tag = match fields.get::<TagPayload>("tag")? {
+        Some(tag) => Some(match tag.tag_type.as_ref() {
+            tag_type @ ("declaration" | "definition") => {
+                let declaration = DeclarationTag {
+                    text: tag.text,
+                    kind: tag.kind.ok_or_else(|| missing_field("kind"))?,
+                    full_range: tag.full_range.ok_or_else(|| missing_field("fullRange"))?,
+                    deprecated: tag.deprecated.unwrap_or_default(),
+                    detail: tag.detail,
+                };
+                match tag_type {
+                    "declaration" => RangeTag::Declaration(declaration),
+                    _ => RangeTag::Definition(declaration),
+                }
+            }
+            "reference" => RangeTag::Reference { text: tag.text },
+            "unknown" => RangeTag::Unknown { text: tag.text },
+            tag_type => {
+                return Err(ProtocolError::new(ErrorKind::JSONParse(format!(
+                    "invalid range tag of type `{}`",
+                    tag_type
+                ))))
+            }
+        }),
+        None => None,
+    };
 
     Ok(Payload::Range(Range {
-        start_line: payload.start.line,
-        start_character: payload.start.character,
-        end_line: payload.end.line,
-        end_character: payload.end.character,
+        start_line: start.line,
+        start_character: start.character,
+        end_line: end.line,
+        end_character: end.character,
+        tag,
     }))
 }
 
-fn deserialize_hover(_line: &[u8]) -> Result<Payload> {
-    Err(anyhow::anyhow!("asd").into())
-}
+fn deserialize_hover(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    #[derive(Deserialize, Serialize)]
+    struct HoverPayload<'a> {
+        #[serde(borrow)]
+        contents: &'a RawValue,
+    }
 
-fn deserialize_moniker(line: &[u8]) -> Result<Payload> {
+    // Either a LanguageString or a MarkupContent, read in a single pass.
     #[derive(Deserialize, Serialize)]
-    struct MonikerPayload {
-        kind: String,
-        scheme: String,
-        identifier: String,
+    struct ObjectPayload {
+        language: Option<String>,
+        kind: Option<MarkupKind>,
+        value: String,
+    }
+
+    // HoverContents and MarkedString are untagged enums, which serde can only match by
+    // buffering the input and trying each variant in turn. The first byte of each value
+    // already tells the string and array forms apart, and the keys tell the object forms
+    // apart.
+    fn hover_contents(raw: &RawValue) -> Result<HoverContents> {
+        Ok(match raw.get().as_bytes().first() {
+            Some(b'[') => HoverContents::Array(
+                serde_json::from_str::<Vec<&RawValue>>(raw.get())?
+                    .into_iter()
+                    .map(|raw| match hover_contents(raw)? {
+                        HoverContents::Scalar(marked) => Ok(marked),
+                        _ => Err(ProtocolError::new(ErrorKind::JSONParse(
+                            "hover contents array must hold marked strings".into(),
+                        ))),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            Some(b'{') => match serde_json::from_str::<ObjectPayload>(raw.get())? {
+                ObjectPayload {
+                    language: Some(language),
+                    value,
+                    ..
+                } => HoverContents::Scalar(MarkedString::LanguageString(LanguageString {
+                    language,
+                    value,
+                })),
+                ObjectPayload {
+                    kind: Some(kind),
+                    value,
+                    ..
+                } => HoverContents::Markup(MarkupContent { kind, value }),
+                _ => {
+                    return Err(ProtocolError::new(ErrorKind::JSONParse(
+                        "hover contents need a `language` or a `kind`".into(),
+                    )))
+                }
+            },
+            _ => HoverContents::Scalar(MarkedString::String(serde_json::from_str(raw.get())?)),
+        })
     }
 
-    let mut payload: MonikerPayload = serde_json::from_slice(line)?;
+    let result: HoverPayload = fields.require("result")?;
+    let contents = hover_contents(result.contents)?;
 
-    if payload.scheme == "" {
-        payload.scheme = "local".into()
+    Ok(Payload::Hover(contents))
+}
+
+fn deserialize_moniker(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    let mut scheme: String = fields.require("scheme")?;
+
+    if scheme.is_empty() {
+        scheme = "local".into()
     }
 
     Ok(Payload::Moniker(Moniker {
-        kind: payload.kind,
-        scheme: payload.scheme,
-        identifier: payload.identifier,
+        kind: fields.get::<MonikerKind>("kind")?,
+        scheme,
+        identifier: fields.require("identifier")?,
+        unique: fields.get::<UniquenessLevel>("unique")?,
+    }))
+}
+
+fn deserialize_package_info(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    Ok(Payload::PackageInformation(PackageInformation {
+        name: fields.require("name")?,
+        manager: fields.require("manager")?,
+        version: fields.get("version")?,
+        uri: fields.get::<Url>("uri")?,
+        contents: fields.get("contents")?,
+        repository: fields.get("repository")?,
     }))
 }
 
-fn deserialize_package_info(line: &[u8]) -> Result<Payload> {
+fn deserialize_diagnostics(_interner: &Interner, fields: &Fields) -> Result<Payload> {
     #[derive(Deserialize, Serialize)]
-    struct PackageInfoPayload {
-        name: String,
-        version: String,
+    struct DiagnosticResultPayload {
+        severity: Option<DiagnosticSeverity>,
+        code: Option<NumberOrString>,
+        message: String,
+        source: Option<String>,
+        range: LSRange,
+        #[serde(rename = "relatedInformation")]
+        related_information: Option<Vec<DiagnosticRelatedInformation>>,
+        tags: Option<Vec<DiagnosticTag>>,
     }
 
-    let payload: PackageInfoPayload = serde_json::from_slice(line)?;
+    let result: Vec<DiagnosticResultPayload> = fields.require("result")?;
 
-    Ok(Payload::PackageInformation(PackageInformation {
-        name: payload.name,
-        version: payload.version,
-    }))
+    let diagnostics = result
+        .into_iter()
+        .map(|diagnostic| Diagnostic {
+            severity: diagnostic.severity,
+            code: diagnostic.code,
+            message: diagnostic.message,
+            source: diagnostic.source.unwrap_or_default(),
+            start_line: diagnostic.range.start.line as u64,
+            start_character: diagnostic.range.start.character as u64,
+            end_line: diagnostic.range.end.line as u64,
+            end_character: diagnostic.range.end.character as u64,
+            related_information: diagnostic.related_information.unwrap_or_default(),
+            tags: diagnostic.tags.unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(Payload::Diagnostics(diagnostics))
+}
+
+fn deserialize_result_set(_interner: &Interner, _fields: &Fields) -> Result<Payload> {
+    Ok(Payload::ResultSet(ResultSet {}))
+}
+
+fn deserialize_definition_result(_interner: &Interner, _fields: &Fields) -> Result<Payload> {
+    Ok(Payload::DefinitionResult)
+}
+
+fn deserialize_declaration_result(_interner: &Interner, _fields: &Fields) -> Result<Payload> {
+    Ok(Payload::DeclarationResult)
+}
+
+fn deserialize_type_definition_result(_interner: &Interner, _fields: &Fields) -> Result<Payload> {
+    Ok(Payload::TypeDefinitionResult)
+}
+
+fn deserialize_reference_result(_interner: &Interner, _fields: &Fields) -> Result<Payload> {
+    Ok(Payload::ReferenceResult)
+}
+
+fn deserialize_implementation_result(_interner: &Interner, _fields: &Fields) -> Result<Payload> {
+    Ok(Payload::ImplementationResult)
 }
 
-fn deserialize_diagnostics(line: &[u8]) -> Result<Payload> {
+fn deserialize_document_symbols(interner: &Interner, fields: &Fields) -> Result<Payload> {
     #[derive(Deserialize, Serialize)]
-    struct DiagnosticPayload {
-        name: String,
-        version: String,
+    struct RangeBasedPayload<'a> {
+        #[serde(borrow)]
+        id: &'a RawValue,
+        children: Option<Vec<RangeBasedPayload<'a>>>,
+    }
+
+    fn convert(
+        interner: &Interner,
+        fields: &Fields,
+        symbols: Vec<RangeBasedPayload>,
+    ) -> Result<Vec<RangeBasedDocumentSymbol>> {
+        symbols
+            .into_iter()
+            .map(|symbol| {
+                Ok(RangeBasedDocumentSymbol {
+                    id: fields.id_of(interner, symbol.id)?,
+                    children: convert(interner, fields, symbol.children.unwrap_or_default())?,
+                })
+            })
+            .collect()
+    }
+
+    #[derive(Deserialize)]
+    struct Probe {
+        id: Option<IgnoredAny>,
+    }
+
+    let result: &RawValue = fields.require("result")?;
+
+    // an untagged enum would have to buffer the input, which rules out borrowing the range
+    // ids, so the form is told from whether the first symbol has an id
+    let elements: Vec<&RawValue> = serde_json::from_str(result.get())?;
+    let range_based = match elements.first() {
+        Some(first) => serde_json::from_str::<Probe>(first.get())?.id.is_some(),
+        None => false,
+    };
+
+    let symbols = if range_based {
+        DocumentSymbols::RangeBased(convert(
+            interner,
+            fields,
+            serde_json::from_str(result.get())?,
+        )?)
+    } else {
+        DocumentSymbols::Symbols(serde_json::from_str::<Vec<DocumentSymbol>>(result.get())?)
+    };
+
+    Ok(Payload::DocumentSymbols(symbols))
+}
+
+fn deserialize_folding_ranges(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    Ok(Payload::FoldingRanges(
+        fields.require::<Vec<FoldingRange>>("result")?,
+    ))
+}
+
+fn deserialize_document_links(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    Ok(Payload::DocumentLinks(
+        fields.require::<Vec<DocumentLink>>("result")?,
+    ))
+}
+
+fn deserialize_project(_interner: &Interner, fields: &Fields) -> Result<Payload> {
+    Ok(Payload::Project(Project {
+        kind: fields.require("kind")?,
+        name: fields.get("name")?.unwrap_or_default(),
+        resource: fields.get::<Url>("resource")?,
+        contents: fields.get("contents")?,
+    }))
+}
+
+fn deserialize_event(interner: &Interner, fields: &Fields) -> Result<Payload> {
+    Ok(Payload::Event(Event {
+        kind: fields.require::<EventKind>("kind")?,
+        scope: fields.require::<EventScope>("scope")?,
+        data: fields
+            .id(interner, "data")?
+            .ok_or_else(|| missing_field("data"))?,
+    }))
+}
+
+/// Interns an id given as raw JSON, i.e. a number or a quoted string.
+fn raw_to_id(interner: &Interner, raw: &RawValue) -> Result<u64> {
+    interner.intern(raw.get().as_bytes())
+}
+
+fn missing_field(name: &str) -> ProtocolError {
+    ProtocolError::new(ErrorKind::JSONParse(format!("missing field `{}`", name)))
+}
+
+/// Converts an error from parsing JSON that starts `base` bytes into the line.
+fn json_error(err: serde_json::Error, base: usize) -> ProtocolError {
+    let offset = match err.line() {
+        // the position is unknown, or past the end of a line with a trailing newline
+        0 | 2.. => None,
+        _ => Some(base + err.column().saturating_sub(1)),
+    };
+
+    let err = ProtocolError::from(err);
+    match offset {
+        Some(offset) => err.at_offset(offset),
+        None => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use lsp_types::FoldingRangeKind;
+
+    fn payload_of(line: &str) -> Payload {
+        let interner = Interner::new();
+        deserialize_element(&interner, line.as_bytes())
+            .unwrap()
+            .payload
+            .expect("element should have a payload")
+    }
+
+    #[test]
+    fn hover_marked_strings() {
+        let line = r#"{"id":6,"type":"vertex","label":"hoverResult","result":{"contents":[{"language":"typescript","value":"function foo(): void"},"docs"]}}"#;
+
+        match payload_of(line) {
+            Payload::Hover(HoverContents::Array(contents)) => {
+                assert_eq!(contents.len(), 2);
+                assert_eq!(contents[1], MarkedString::String("docs".into()));
+            }
+            _ => panic!("expected marked string hover"),
+        }
+    }
+
+    #[test]
+    fn hover_markup_content() {
+        let line = r#"{"id":6,"type":"vertex","label":"hoverResult","result":{"contents":{"kind":"markdown","value":"```rust\nfn foo()\n```"}}}"#;
+
+        match payload_of(line) {
+            Payload::Hover(HoverContents::Markup(markup)) => {
+                assert_eq!(markup.kind, MarkupKind::Markdown);
+                assert_eq!(markup.value, "```rust\nfn foo()\n```");
+            }
+            _ => panic!("expected markup hover"),
+        }
+
+        let line = r#"{"id":7,"type":"vertex","label":"hoverResult","result":{"contents":{"kind":"plaintext","value":"fn foo()"}}}"#;
+
+        match payload_of(line) {
+            Payload::Hover(HoverContents::Markup(markup)) => {
+                assert_eq!(markup.kind, MarkupKind::PlainText)
+            }
+            _ => panic!("expected markup hover"),
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let line = r#"{"id":8,"type":"vertex","label":"diagnosticResult","result":[{"severity":1,"code":2322,"message":"type mismatch","source":"tsc","range":{"start":{"line":1,"character":5},"end":{"line":1,"character":9}},"tags":[1]},{"code":"unused","message":"unused variable","range":{"start":{"line":3,"character":0},"end":{"line":4,"character":2}},"relatedInformation":[{"location":{"uri":"file:///sample.ts","range":{"start":{"line":0,"character":0},"end":{"line":0,"character":3}}},"message":"declared here"}]}]}"#;
+
+        let diagnostics = match payload_of(line) {
+            Payload::Diagnostics(diagnostics) => diagnostics,
+            _ => panic!("expected diagnostics"),
+        };
+
+        assert_eq!(diagnostics.len(), 2);
+
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::Error));
+        assert_eq!(diagnostics[0].code, Some(NumberOrString::Number(2322)));
+        assert_eq!(diagnostics[0].source, "tsc");
+        assert_eq!(
+            (diagnostics[0].start_line, diagnostics[0].start_character),
+            (1, 5)
+        );
+        assert_eq!(diagnostics[0].tags, vec![DiagnosticTag::Unnecessary]);
+
+        assert_eq!(diagnostics[1].severity, None);
+        assert_eq!(
+            diagnostics[1].code,
+            Some(NumberOrString::String("unused".into()))
+        );
+        assert_eq!(diagnostics[1].source, "");
+        assert_eq!(
+            (diagnostics[1].end_line, diagnostics[1].end_character),
+            (4, 2)
+        );
+        assert_eq!(diagnostics[1].related_information.len(), 1);
+        assert_eq!(
+            diagnostics[1].related_information[0].message,
+            "declared here"
+        );
+    }
+
+    #[test]
+    fn result_sets() {
+        let payload = |label: &str| {
+            payload_of(&format!(
+                r#"{{"id":9,"type":"vertex","label":"{}"}}"#,
+                label
+            ))
+        };
+
+        assert!(matches!(payload("resultSet"), Payload::ResultSet(_)));
+        assert!(matches!(
+            payload("definitionResult"),
+            Payload::DefinitionResult
+        ));
+        assert!(matches!(
+            payload("declarationResult"),
+            Payload::DeclarationResult
+        ));
+        assert!(matches!(
+            payload("typeDefinitionResult"),
+            Payload::TypeDefinitionResult
+        ));
+        assert!(matches!(
+            payload("referenceResult"),
+            Payload::ReferenceResult
+        ));
+        assert!(matches!(
+            payload("implementationResult"),
+            Payload::ImplementationResult
+        ));
+    }
+
+    #[test]
+    fn item_edge_property() {
+        let line = r#"{"id":20,"type":"edge","label":"item","outV":10,"inVs":[3,4],"document":2,"property":"referenceResults"}"#;
+
+        match payload_of(line) {
+            Payload::Edge(edge) => {
+                assert_eq!(edge.out_v, 10);
+                assert_eq!(edge.in_vs, vec![3, 4]);
+                assert_eq!(edge.document, 2);
+                assert_eq!(edge.property, Some(ItemProperty::ReferenceResults));
+            }
+            _ => panic!("expected edge"),
+        }
+
+        let line = r#"{"id":21,"type":"edge","label":"item","outV":10,"inVs":[5],"document":2}"#;
+
+        match payload_of(line) {
+            Payload::Edge(edge) => assert_eq!(edge.property, None),
+            _ => panic!("expected edge"),
+        }
+
+        let line = r#"{"id":22,"type":"edge","label":"item","outV":10,"inVs":[5],"document":2,"property":"vendorLinks"}"#;
+
+        match payload_of(line) {
+            Payload::Edge(edge) => assert_eq!(
+                edge.property,
+                Some(ItemProperty::Unknown("vendorLinks".into()))
+            ),
+            _ => panic!("expected edge"),
+        }
+    }
+
+    #[test]
+    fn range_tags() {
+        let line = r#"{"id":11,"type":"vertex","label":"range","start":{"line":4,"character":9},"end":{"line":4,"character":12},"tag":{"type":"definition","text":"foo","kind":12,"fullRange":{"start":{"line":4,"character":0},"end":{"line":6,"character":1}},"deprecated":true}}"#;
+
+        match payload_of(line) {
+            Payload::Range(range) => {
+                assert_eq!((range.start_line, range.start_character), (4, 9));
+                assert_eq!((range.end_line, range.end_character), (4, 12));
+                match range.tag {
+                    Some(RangeTag::Definition(tag)) => {
+                        assert_eq!(tag.text, "foo");
+                        assert_eq!(tag.kind, SymbolKind::Function);
+                        assert_eq!(tag.full_range.end.line, 6);
+                        assert!(tag.deprecated);
+                        assert_eq!(tag.detail, None);
+                    }
+                    _ => panic!("expected definition tag"),
+                }
+            }
+            _ => panic!("expected range"),
+        }
+
+        let line = r#"{"id":12,"type":"vertex","label":"range","start":{"line":8,"character":2},"end":{"line":8,"character":5},"tag":{"type":"reference","text":"foo"}}"#;
+
+        match payload_of(line) {
+            Payload::Range(range) => {
+                assert_eq!(range.tag, Some(RangeTag::Reference { text: "foo".into() }))
+            }
+            _ => panic!("expected range"),
+        }
+
+        let line = r#"{"id":13,"type":"vertex","label":"range","start":{"line":8,"character":2},"end":{"line":8,"character":5}}"#;
+
+        match payload_of(line) {
+            Payload::Range(range) => assert_eq!(range.tag, None),
+            _ => panic!("expected range"),
+        }
+    }
+
+    #[test]
+    fn document_symbols() {
+        let line = r#"{"id":30,"type":"vertex","label":"documentSymbolResult","result":[{"name":"Foo","kind":5,"range":{"start":{"line":0,"character":0},"end":{"line":9,"character":1}},"selectionRange":{"start":{"line":0,"character":6},"end":{"line":0,"character":9}},"children":[{"name":"bar","kind":6,"range":{"start":{"line":1,"character":2},"end":{"line":3,"character":3}},"selectionRange":{"start":{"line":1,"character":2},"end":{"line":1,"character":5}}}]}]}"#;
+
+        match payload_of(line) {
+            Payload::DocumentSymbols(DocumentSymbols::Symbols(symbols)) => {
+                assert_eq!(symbols.len(), 1);
+                assert_eq!(symbols[0].name, "Foo");
+                assert_eq!(symbols[0].kind, SymbolKind::Class);
+                let children = symbols[0].children.as_ref().unwrap();
+                assert_eq!(children[0].name, "bar");
+            }
+            _ => panic!("expected document symbols"),
+        }
+
+        let line = r#"{"id":31,"type":"vertex","label":"documentSymbolResult","result":[{"id":11,"children":[{"id":12},{"id":13,"children":[]}]}]}"#;
+
+        match payload_of(line) {
+            Payload::DocumentSymbols(DocumentSymbols::RangeBased(symbols)) => {
+                assert_eq!(symbols.len(), 1);
+                assert_eq!(symbols[0].id, 11);
+                let children: Vec<u64> = symbols[0].children.iter().map(|c| c.id).collect();
+                assert_eq!(children, vec![12, 13]);
+            }
+            _ => panic!("expected range based document symbols"),
+        }
+
+        // a malformed symbol is reported as such rather than as a range based one
+        let line = r#"{"id":32,"type":"vertex","label":"documentSymbolResult","result":[{"name":"Foo","kind":5,"range":{"start":{"line":0,"character":0},"end":{"line":9,"character":1}}}]}"#;
+        let err = match deserialize_element(&Interner::new(), line.as_bytes()) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        };
+        assert!(err.contains("missing field `selectionRange`"), "{}", err);
+    }
+
+    #[test]
+    fn folding_ranges() {
+        let line = r#"{"id":32,"type":"vertex","label":"foldingRangeResult","result":[{"startLine":0,"endLine":9},{"startLine":1,"startCharacter":4,"endLine":3,"endCharacter":2,"kind":"comment"}]}"#;
+
+        match payload_of(line) {
+            Payload::FoldingRanges(ranges) => {
+                assert_eq!(ranges.len(), 2);
+                assert_eq!((ranges[0].start_line, ranges[0].end_line), (0, 9));
+                assert_eq!(ranges[1].start_character, Some(4));
+                assert_eq!(ranges[1].kind, Some(FoldingRangeKind::Comment));
+            }
+            _ => panic!("expected folding ranges"),
+        }
+    }
+
+    #[test]
+    fn document_links() {
+        let line = r#"{"id":33,"type":"vertex","label":"documentLinkResult","result":[{"range":{"start":{"line":0,"character":8},"end":{"line":0,"character":20}},"target":"file:///project/lib.ts"},{"range":{"start":{"line":2,"character":0},"end":{"line":2,"character":4}}}]}"#;
+
+        match payload_of(line) {
+            Payload::DocumentLinks(links) => {
+                assert_eq!(links.len(), 2);
+                assert_eq!(
+                    links[0].target,
+                    Some(Url::parse("file:///project/lib.ts").unwrap())
+                );
+                assert_eq!(links[0].range.start.character, 8);
+                assert_eq!(links[1].target, None);
+            }
+            _ => panic!("expected document links"),
+        }
+    }
+
+    #[test]
+    fn project() {
+        let line = r#"{"id":2,"type":"vertex","label":"project","kind":"typescript","name":"sample","resource":"file:///project/tsconfig.json"}"#;
+
+        match payload_of(line) {
+            Payload::Project(project) => {
+                assert_eq!(project.kind, "typescript");
+                assert_eq!(project.name, "sample");
+                assert_eq!(
+                    project.resource,
+                    Some(Url::parse("file:///project/tsconfig.json").unwrap())
+                );
+                assert_eq!(project.contents, None);
+            }
+            _ => panic!("expected project"),
+        }
+    }
+
+    #[test]
+    fn events() {
+        let line = r#"{"id":5,"type":"vertex","label":"$event","kind":"begin","scope":"document","data":4}"#;
+
+        match payload_of(line) {
+            Payload::Event(event) => {
+                assert_eq!(event.kind, EventKind::Begin);
+                assert_eq!(event.scope, EventScope::Document);
+                assert_eq!(event.data, 4);
+            }
+            _ => panic!("expected event"),
+        }
+
+        let line =
+            r#"{"id":55,"type":"vertex","label":"$event","kind":"end","scope":"project","data":2}"#;
+
+        match payload_of(line) {
+            Payload::Event(event) => {
+                assert_eq!(event.kind, EventKind::End);
+                assert_eq!(event.scope, EventScope::Project);
+                assert_eq!(event.data, 2);
+            }
+            _ => panic!("expected event"),
+        }
+
+        let line = r#"{"id":56,"type":"vertex","label":"$event","kind":"begin","scope":"monikerAttach","data":40}"#;
+
+        match payload_of(line) {
+            Payload::Event(event) => {
+                assert_eq!(event.kind, EventKind::Begin);
+                assert_eq!(event.scope, EventScope::Unknown("monikerAttach".into()));
+            }
+            _ => panic!("expected event"),
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        let line = r#"{"id":1,"type":"vertex","label":"metaData","version":"0.4.3","projectRoot":"file:///project","positionEncoding":"utf-16","toolInfo":{"name":"lsif-tsc","version":"0.6.0","args":["-p","."]}}"#;
+
+        match payload_of(line) {
+            Payload::MetaData(meta) => {
+                assert_eq!(meta.version, "0.4.3");
+                assert_eq!(meta.project_root, "file:///project");
+                assert_eq!(meta.position_encoding, "utf-16");
+                let tool_info = meta.tool_info.unwrap();
+                assert_eq!(tool_info.name, "lsif-tsc");
+                assert_eq!(tool_info.version.as_deref(), Some("0.6.0"));
+                assert_eq!(tool_info.args, vec!["-p", "."]);
+            }
+            _ => panic!("expected metadata"),
+        }
+
+        let line = r#"{"id":1,"type":"vertex","label":"metaData","version":"0.5.0-next.1","projectRoot":"file:///project"}"#;
+
+        match payload_of(line) {
+            Payload::MetaData(meta) => {
+                assert_eq!(meta.position_encoding, "utf-16");
+                assert!(meta.tool_info.is_none());
+            }
+            _ => panic!("expected metadata"),
+        }
+
+        let line = r#"{"id":1,"type":"vertex","label":"metaData","version":"0.4.3","projectRoot":"file:///project","toolInfo":{"name":"lsif-go"}}"#;
+
+        match payload_of(line) {
+            Payload::MetaData(meta) => assert_eq!(meta.tool_info.unwrap().version, None),
+            _ => panic!("expected metadata"),
+        }
     }
 
-    let _payload: DiagnosticPayload = serde_json::from_slice(line)?;
+    #[test]
+    fn metadata_unsupported_version() {
+        let interner = Interner::new();
 
-    Ok(Payload::Diagnostics(Vec::new()))
+        for version in &["1.0.0", "0.3.1", "latest"] {
+            let line = format!(
+                r#"{{"id":1,"type":"vertex","label":"metaData","version":"{}","projectRoot":"file:///project"}}"#,
+                version
+            );
+
+            match deserialize_element(&interner, line.as_bytes()) {
+                Err(ProtocolError {
+                    kind: ErrorKind::UnsupportedVersion(v),
+                    ..
+                }) => assert_eq!(&v, version),
+                _ => panic!("expected unsupported version error for {}", version),
+            }
+        }
+    }
+
+    #[test]
+    fn moniker() {
+        let line = r#"{"id":40,"type":"vertex","label":"moniker","kind":"export","scheme":"npm","identifier":"sample:foo","unique":"scheme"}"#;
+
+        match payload_of(line) {
+            Payload::Moniker(moniker) => {
+                assert_eq!(moniker.kind, Some(MonikerKind::Export));
+                assert_eq!(moniker.scheme, "npm");
+                assert_eq!(moniker.identifier, "sample:foo");
+                assert_eq!(moniker.unique, Some(UniquenessLevel::Scheme));
+            }
+            _ => panic!("expected moniker"),
+        }
+
+        let line = r#"{"id":41,"type":"vertex","label":"moniker","kind":"local","scheme":"","identifier":"x"}"#;
+
+        match payload_of(line) {
+            Payload::Moniker(moniker) => {
+                assert_eq!(moniker.kind, Some(MonikerKind::Local));
+                assert_eq!(moniker.scheme, "local");
+                assert_eq!(moniker.unique, None);
+            }
+            _ => panic!("expected moniker"),
+        }
+
+        let line = r#"{"id":43,"type":"vertex","label":"moniker","scheme":"tsc","identifier":"a:b","unique":"document"}"#;
+
+        match payload_of(line) {
+            Payload::Moniker(moniker) => {
+                assert_eq!(moniker.kind, None);
+                assert_eq!(moniker.unique, Some(UniquenessLevel::Document));
+            }
+            _ => panic!("expected moniker"),
+        }
+    }
+
+    #[test]
+    fn package_information() {
+        let line = r#"{"id":42,"type":"vertex","label":"packageInformation","name":"sample","manager":"npm","version":"1.2.3","uri":"file:///project/package.json","repository":{"type":"git","url":"https://github.com/example/sample","commitId":"deadbeef"}}"#;
+
+        match payload_of(line) {
+            Payload::PackageInformation(info) => {
+                assert_eq!(info.name, "sample");
+                assert_eq!(info.manager, "npm");
+                assert_eq!(info.version.as_deref(), Some("1.2.3"));
+                assert_eq!(
+                    info.uri,
+                    Some(Url::parse("file:///project/package.json").unwrap())
+                );
+                assert_eq!(info.contents, None);
+                assert_eq!(
+                    info.repository,
+                    Some(Repository {
+                        repo_type: "git".into(),
+                        url: "https://github.com/example/sample".into(),
+                        commit_id: Some("deadbeef".into()),
+                        directory: None,
+                    })
+                );
+            }
+            _ => panic!("expected package information"),
+        }
+
+        let line = r#"{"id":43,"type":"vertex","label":"packageInformation","name":"sample","manager":"npm"}"#;
+
+        match payload_of(line) {
+            Payload::PackageInformation(info) => assert_eq!(info.version, None),
+            _ => panic!("expected package information"),
+        }
+
+        let err = match deserialize_element(
+            &Interner::new(),
+            br#"{"id":44,"type":"vertex","label":"packageInformation","name":"sample","version":"1.2.3"}"#,
+        ) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        };
+        assert!(err.ends_with("missing field `manager`"), "{}", err);
+    }
+
+    #[test]
+    fn element_kinds() {
+        let interner = Interner::new();
+        let kind_of = |line: &str| {
+            deserialize_element(&interner, line.as_bytes())
+                .unwrap()
+                .kind
+        };
+
+        assert_eq!(
+            kind_of(r#"{"id":1,"type":"vertex","label":"resultSet"}"#),
+            ElementKind::Vertex(VertexLabel::ResultSet)
+        );
+        assert_eq!(
+            kind_of(r#"{"id":2,"type":"edge","label":"textDocument/references","outV":1,"inV":3}"#),
+            ElementKind::Edge(EdgeLabel::TextDocReferences)
+        );
+
+        let kind = kind_of(r#"{"id":3,"type":"vertex","label":"vendorThing"}"#);
+        assert_eq!(
+            kind,
+            ElementKind::Vertex(VertexLabel::Unknown("vendorThing".into()))
+        );
+        assert_eq!(kind.label(), "vendorThing");
+
+        assert!(deserialize_element(
+            &interner,
+            br#"{"id":4,"type":"hyperedge","label":"contains"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn header_anywhere_and_escaped() {
+        // header fields after the payload, and a label that can't be borrowed
+        let line = r#"{"start":{"line":1,"character":2},"end":{"line":1,"character":4},"label":"r\u0061nge","type":"vertex","id":3}"#;
+
+        let element = deserialize_element(&Interner::new(), line.as_bytes()).unwrap();
+        assert_eq!(element.id, 3);
+        assert_eq!(element.kind, ElementKind::Vertex(VertexLabel::Range));
+        match element.payload {
+            Some(Payload::Range(range)) => assert_eq!(range.end_character, 4),
+            _ => panic!("expected range"),
+        }
+
+        // a tag type that can't be borrowed
+        let line = r#"{"id":4,"type":"vertex","label":"range","start":{"line":1,"character":2},"end":{"line":1,"character":4},"tag":{"type":"refer\u0065nce","text":"foo"}}"#;
+
+        match payload_of(line) {
+            Payload::Range(range) => {
+                assert_eq!(range.tag, Some(RangeTag::Reference { text: "foo".into() }))
+            }
+            _ => panic!("expected range"),
+        }
+    }
+
+    #[test]
+    fn missing_fields() {
+        let interner = Interner::new();
+
+        assert!(deserialize_element(&interner, br#"{"type":"vertex","label":"range"}"#).is_err());
+        assert!(deserialize_element(
+            &interner,
+            br#"{"id":1,"type":"vertex","label":"range","start":{"line":1,"character":2}}"#
+        )
+        .is_err());
+        assert!(deserialize_element(
+            &interner,
+            br#"{"id":2,"type":"edge","label":"next","inV":1}"#
+        )
+        .is_err());
+
+        let message = |line: &[u8]| match deserialize_element(&interner, line) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        };
+        assert!(message(
+            br#"{"id":3,"type":"vertex","label":"range","start":{"line":1,"character":2},"end":{"line":1,"character":5},"tag":{"type":"definition","text":"foo","kind":12}}"#
+        )
+        .ends_with("missing field `fullRange`"));
+        assert!(message(
+            br#"{"id":4,"type":"vertex","label":"range","start":{"line":1,"character":2},"end":{"line":1,"character":5},"tag":{"type":"declaration","text":"foo","fullRange":{"start":{"line":1,"character":0},"end":{"line":1,"character":5}}}}"#
+        )
+        .ends_with("missing field `kind`"));
+    }
+
+    #[test]
+    fn located_errors() {
+        let interner = Interner::new();
+        let error = |line: &str| match deserialize_element(&interner, line.as_bytes()) {
+            Ok(_) => panic!("expected an error for {}", line),
+            Err(err) => err,
+        };
+
+        for id in &["1.5", "-3", "null", "{}"] {
+            let err = error(&format!(
+                r#"{{"id":{},"type":"vertex","label":"range"}}"#,
+                id
+            ));
+            assert!(matches!(err.kind, ErrorKind::IDParse(_)), "{}", id);
+            assert_eq!(err.location.id.as_deref(), Some(*id));
+            assert_eq!(err.location.label.as_deref(), Some("range"));
+            assert_eq!(err.location.offset, Some(6));
+        }
+
+        let line = r#"{"id":"a","type":"vertex","label":"range","start":{"line":"x","character":0},"end":{"line":0,"character":1}}"#;
+        let err = error(line);
+        let offset = err.location.offset.unwrap() as usize;
+        assert!(offset > line.find("start").unwrap() && offset < line.find("end").unwrap());
+        assert!(err.to_string().starts_with(&format!(
+            r#"byte {}, element "a" (range): field `start`: invalid type"#,
+            offset
+        )));
+
+        let line = r#"{"id":2,"type":"edge","label":"next","outV":3,"inVs":[4,5.5]}"#;
+        let err = error(line);
+        assert_eq!(err.location.offset, line.find("5.5").map(|at| at as u64));
+        assert_eq!(err.location.label.as_deref(), Some("next"));
+
+        let line = r#"{"id":3,"type":"vertex","label":"documentSymbolResult","result":[{"id":11,"children":[{"id":1.5}]}]}"#;
+        let err = error(line);
+        assert!(matches!(err.kind, ErrorKind::IDParse(_)));
+        assert_eq!(err.location.offset, line.find("1.5").map(|at| at as u64));
+
+        let err = error(r#"{"id":1,"type":"vertex","label":"range"}"#).at_line(3, 100);
+        assert_eq!(
+            err.to_string(),
+            "line 3, byte 100, element 1 (range): missing field `start`"
+        );
+
+        let err = error(r#"{"id":1,"type":"vertex","#).at_line(2, 40);
+        assert_eq!(err.location.line, Some(2));
+        assert!(err.location.offset.unwrap() >= 40);
+        assert_eq!(err.location.id, None);
+    }
+
+    #[test]
+    fn hover_scalar() {
+        let line = r#"{"id":6,"type":"vertex","label":"hoverResult","result":{"contents":{"language":"rust","value":"fn foo()"}}}"#;
+
+        match payload_of(line) {
+            Payload::Hover(HoverContents::Scalar(MarkedString::LanguageString(s))) => {
+                assert_eq!(s.language, "rust")
+            }
+            _ => panic!("expected language string hover"),
+        }
+
+        let line = r#"{"id":7,"type":"vertex","label":"hoverResult","result":{"contents":"docs"}}"#;
+
+        match payload_of(line) {
+            Payload::Hover(HoverContents::Scalar(MarkedString::String(s))) => {
+                assert_eq!(s, "docs")
+            }
+            _ => panic!("expected plain string hover"),
+        }
+    }
+
+    #[test]
+    fn registry() {
+        struct Vendor {
+            build: u64,
+        }
+
+        fn vendor(_: &Interner, fields: &Fields) -> Result<Payload> {
+            Ok(Payload::Custom(Arc::new(Vendor {
+                build: fields.require("build")?,
+            })))
+        }
+
+        fn attach(interner: &Interner, fields: &Fields) -> Result<Payload> {
+            Ok(Payload::Custom(Arc::new(fields.id(interner, "outV")?)))
+        }
+
+        // deserializers can carry their own configuration
+        let scale = 10;
+
+        let mut registry = Registry::new();
+        registry
+            .register_vertex("vendorMetadata", vendor)
+            .register_vertex("vendorScaled", move |_: &Interner, fields: &Fields| {
+                Ok(Payload::Custom(Arc::new(
+                    scale * fields.require::<u64>("build")?,
+                )))
+            })
+            .register_edge("attach", attach);
+
+        let interner = Interner::new();
+        let deserialize = |line: &str| {
+            deserialize_element_with(&registry, &interner, line.as_bytes())
+                .unwrap()
+                .payload
+        };
+
+        match deserialize(r#"{"id":1,"type":"vertex","label":"vendorMetadata","build":42}"#) {
+            Some(Payload::Custom(custom)) => {
+                assert_eq!(custom.downcast_ref::<Vendor>().unwrap().build, 42)
+            }
+            _ => panic!("expected custom vertex payload"),
+        }
+
+        match deserialize(r#"{"id":7,"type":"vertex","label":"vendorScaled","build":4}"#) {
+            Some(Payload::Custom(custom)) => assert_eq!(custom.downcast_ref::<u64>(), Some(&40)),
+            _ => panic!("expected custom vertex payload"),
+        }
+
+        match deserialize(r#"{"id":2,"type":"edge","label":"attach","outV":1,"inV":3}"#) {
+            Some(Payload::Custom(custom)) => {
+                assert_eq!(custom.downcast_ref::<Option<u64>>(), Some(&Some(1)))
+            }
+            _ => panic!("expected custom edge payload"),
+        }
+
+        // labels without a registration still use the built-ins
+        assert!(matches!(
+            deserialize(r#"{"id":3,"type":"vertex","label":"resultSet"}"#),
+            Some(Payload::ResultSet(_))
+        ));
+        assert!(matches!(
+            deserialize(r#"{"id":4,"type":"edge","label":"next","outV":3,"inV":1}"#),
+            Some(Payload::Edge(_))
+        ));
+
+        // a known label spelled as an unknown one replaces the built-in
+        let mut registry = Registry::new();
+        registry
+            .register_vertex(VertexLabel::Unknown("resultSet".into()), vendor)
+            .register_edge(EdgeLabel::Unknown("next".into()), attach);
+
+        let deserialize = |line: &str| {
+            deserialize_element_with(&registry, &interner, line.as_bytes())
+                .unwrap()
+                .payload
+        };
+        assert!(matches!(
+            deserialize(r#"{"id":5,"type":"vertex","label":"resultSet","build":7}"#),
+            Some(Payload::Custom(_))
+        ));
+        assert!(matches!(
+            deserialize(r#"{"id":6,"type":"edge","label":"next","outV":5,"inV":1}"#),
+            Some(Payload::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn mixed_id_dump() {
+        let interner = Interner::new();
+        let lines = [
+            r#"{"id":1,"type":"vertex","label":"resultSet"}"#,
+            r#"{"id":"range-1","type":"vertex","label":"range","start":{"line":0,"character":0},"end":{"line":0,"character":3}}"#,
+            r#"{"id":"2","type":"edge","label":"next","outV":"range-1","inV":1}"#,
+        ];
+
+        let elements: Vec<Element> = lines
+            .iter()
+            .map(|line| deserialize_element(&interner, line.as_bytes()).unwrap())
+            .collect();
+
+        assert_eq!(elements[0].id, 1);
+        assert_eq!(elements[2].id, 2);
+        assert!(Interner::is_interned(elements[1].id));
+
+        match &elements[2].payload {
+            Some(Payload::Edge(edge)) => {
+                assert_eq!(edge.out_v, elements[1].id);
+                assert_eq!(edge.in_v, elements[0].id);
+            }
+            _ => panic!("expected edge"),
+        }
+    }
 }
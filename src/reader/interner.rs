@@ -1,57 +1,196 @@
-use std::collections::HashMap;
-use std::marker::Sync;
-use std::sync::{Arc, Mutex};
+use serde::{Serialize, Serializer};
+
+use super::types::Result;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Ids at or above this value are handed out by the interner for ids that aren't numbers
+/// below it, so they can never collide with an id taken verbatim from the input.
+pub const INTERNED_ID_BASE: u64 = 1 << 63;
+
+/// Number of independently locked shards the interned ids are spread over.
+const SHARD_COUNT: usize = 32;
 
 /// Interner converts strings into unique identifers. Submitting the same byte value to
 /// the interner will result in the same identifier being produced. Each unique input is
-/// guaranteed to have a unique output (no two inputs share the same identifier). The
-/// identifier space of two distinct interner instances may overlap.
+/// guaranteed to have a unique output (no two inputs share the same identifier).
+///
+/// Numeric ids below `INTERNED_ID_BASE` map to their own value, whether or not they are
+/// quoted, so `1` and `"1"` name the same element. Which of them were quoted is recorded
+/// for `lookup`. Every other id is assigned a free
+/// identifier from `INTERNED_ID_BASE` upwards, which keeps dumps mixing numeric and
+/// string ids collision free.
 ///
-/// Assumption: The output of LSIF indexers will not generally mix types of identifiers.
-/// If integers are used, they are used for all ids. If strings are used, they are used
-/// for all ids.
+/// Strings are spread over `SHARD_COUNT` shards by hash, each behind its own lock, so
+/// workers interning different ids rarely wait on each other. A shard only hands out
+/// identifiers congruent to its index modulo `SHARD_COUNT`, which keeps identifiers from
+/// different shards distinct without any shared counter.
+///
+/// The assigned part of the identifier space of two distinct interner instances may
+/// overlap. Readers whose output is merged should share a single interner (clones share
+/// their state).
 #[derive(Clone)]
 pub struct Interner {
-    map: Arc<Mutex<HashMap<String, u64>>>,
+    shards: Arc<[RwLock<Shard>]>,
+}
+
+#[derive(Default)]
+struct Shard {
+    map: HashMap<Arc<str>, u64>,
+    /// The input of every id assigned by this shard, in assignment order.
+    raw: Vec<Arc<str>>,
+    /// The numeric ids congruent to this shard's index that were seen as strings.
+    quoted: HashSet<u64>,
+}
+
+/// An id as it appeared in the input, recovered with `Interner::lookup`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RawId {
+    Number(u64),
+    String(Arc<str>),
 }
 
-unsafe impl Sync for Interner {}
+/// Formats the id as JSON, i.e. with quotes around string ids.
+impl fmt::Display for RawId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawId::Number(id) => write!(f, "{}", id),
+            RawId::String(id) => write!(f, "{}", serde_json::to_string(&**id).unwrap()),
+        }
+    }
+}
+
+impl Serialize for RawId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            RawId::Number(id) => serializer.serialize_u64(*id),
+            RawId::String(id) => serializer.serialize_str(id),
+        }
+    }
+}
 
 impl Interner {
     pub fn new() -> Interner {
         Interner {
-            map: Arc::new(Mutex::new(HashMap::new())),
+            shards: (0..SHARD_COUNT)
+                .map(|_| RwLock::new(Shard::default()))
+                .collect(),
         }
     }
 
     /// Intern returns the unique identifier for the given byte value. The byte value should
     /// be a raw LSIF input identifier, which should be a JSON-encoded number or quoted string.
+    /// Input that isn't UTF-8, or isn't a number or a string, is an `IDParse` error.
     /// This method is safe to call from multiple goroutines.
-    pub fn intern(&self, raw: &[u8]) -> Result<u64, std::num::ParseIntError> {
+    pub fn intern(&self, raw: &[u8]) -> Result<u64> {
         if raw.is_empty() {
             return Ok(0);
         }
 
-        if raw[0] != b'"' {
-            unsafe { return String::from_utf8_unchecked(raw.to_vec()).parse::<u64>() }
-        }
+        // a lone quote isn't a quoted string, and fails to parse as a number below
+        let quoted = raw.len() >= 2 && raw[0] == b'"' && raw[raw.len() - 1] == b'"';
+        let s = if quoted {
+            std::str::from_utf8(&raw[1..raw.len() - 1])?
+        } else {
+            std::str::from_utf8(raw)?
+        };
 
-        let s = unsafe { String::from_utf8_unchecked(raw[1..raw.len() - 1].to_vec()) };
+        // escaped strings are decoded so that every spelling of an id interns the same,
+        // including numbers spelled with escapes
+        let s = if quoted && s.contains('\\') {
+            serde_json::from_slice::<String>(raw)
+                .map(Cow::Owned)
+                .unwrap_or(Cow::Borrowed(s))
+        } else {
+            Cow::Borrowed(s)
+        };
 
         match s.parse::<u64>() {
-            Ok(num) => return Ok(num),
-            Err(_) => {}
+            Ok(num) if num < INTERNED_ID_BASE && quoted => {
+                self.record_quoted(num);
+                return Ok(num);
+            }
+            Ok(num) if num < INTERNED_ID_BASE => return Ok(num),
+            Err(err) if !quoted => return Err(err.into()),
+            _ => {}
         }
 
-        let mut map = self.map.lock().unwrap();
-        if map.contains_key(&s) {
-            return Ok(*map.get(&s).unwrap());
+        let index = shard_index(&s);
+        let shard = &self.shards[index];
+
+        // most ids are seen several times, so check under the shared lock first
+        if let Some(id) = shard.read().unwrap().map.get(s.as_ref()) {
+            return Ok(*id);
         }
 
-        let id: u64 = (map.len() + 1) as u64;
-        map.insert(s, id);
+        let mut shard = shard.write().unwrap();
+        if let Some(id) = shard.map.get(s.as_ref()) {
+            return Ok(*id);
+        }
+
+        let id = INTERNED_ID_BASE + (shard.raw.len() * SHARD_COUNT + index) as u64;
+        let s: Arc<str> = s.into();
+        shard.map.insert(s.clone(), id);
+        shard.raw.push(s);
         Ok(id)
     }
+
+    /// Remembers that the numeric id `num` appeared as a string.
+    fn record_quoted(&self, num: u64) {
+        let shard = &self.shards[num as usize % SHARD_COUNT];
+        if !shard.read().unwrap().quoted.contains(&num) {
+            shard.write().unwrap().quoted.insert(num);
+        }
+    }
+
+    /// Returns the input id that `id` was produced from. Ids below `INTERNED_ID_BASE` are
+    /// taken verbatim from the input and are returned as numbers, or as strings if they
+    /// were ever seen quoted: a dump spelling one id both ways gets the quoted form back.
+    /// Returns `None` for ids at or above `INTERNED_ID_BASE` that this interner didn't
+    /// assign.
+    pub fn lookup(&self, id: u64) -> Option<RawId> {
+        if !Interner::is_interned(id) {
+            let shard = self.shards[id as usize % SHARD_COUNT].read().unwrap();
+            return Some(if shard.quoted.contains(&id) {
+                RawId::String(id.to_string().into())
+            } else {
+                RawId::Number(id)
+            });
+        }
+
+        let offset = (id - INTERNED_ID_BASE) as usize;
+        let shard = self.shards[offset % SHARD_COUNT].read().unwrap();
+        shard
+            .raw
+            .get(offset / SHARD_COUNT)
+            .map(|raw| RawId::String(raw.clone()))
+    }
+
+    /// Whether `id` was assigned by an interner rather than taken from the input.
+    pub fn is_interned(id: u64) -> bool {
+        id >= INTERNED_ID_BASE
+    }
+}
+
+/// Picks the shard for `s` by hashing it a word at a time, which is cheap for the short ids
+/// found in dumps. The maps within a shard still use the default hasher.
+fn shard_index(s: &str) -> usize {
+    let mut hash = s.len() as u64;
+    for chunk in s.as_bytes().chunks(8) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        hash = (hash.rotate_left(5) ^ u64::from_le_bytes(word)).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+    (hash >> 32) as usize % SHARD_COUNT
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
 }
 
 #[cfg(test)]
@@ -147,4 +286,118 @@ mod tests {
 
         assert_eq!(results.len(), values.len() - 2);
     }
+
+    #[test]
+    fn mixed_ids_dont_collide() {
+        let values = string_vec_to_bytes(vec![
+            "1", r#""2""#, "3", r#""abc""#, r#""def""#, r#""4""#, "0", r#""""#,
+        ]);
+
+        let results = compare_from_vec(&values).unwrap();
+
+        assert_eq!(results.len(), values.len());
+    }
+
+    #[test]
+    fn quoted_and_bare_numbers_agree() {
+        let interner = Interner::new();
+
+        assert_eq!(interner.intern(b"42").unwrap(), 42);
+        assert_eq!(interner.intern(br#""42""#).unwrap(), 42);
+    }
+
+    #[test]
+    fn string_ids_use_interned_space() {
+        let interner = Interner::new();
+
+        let abc = interner.intern(br#""abc""#).unwrap();
+        assert!(Interner::is_interned(abc));
+        assert!(!Interner::is_interned(interner.intern(b"1").unwrap()));
+
+        // numbers that would land in the interned space are interned too, by their text
+        let big = interner.intern(b"9223372036854775808").unwrap();
+        assert!(Interner::is_interned(big));
+        assert_ne!(big, abc);
+        assert_eq!(interner.intern(br#""9223372036854775808""#).unwrap(), big);
+
+        assert!(interner.intern(b"-1").is_err());
+        assert!(interner.intern(b"1.5").is_err());
+        assert!(interner.intern(b"\"").is_err());
+        assert!(interner.intern(b"\"\xff\"").is_err());
+        assert!(interner.intern(b"\xc3").is_err());
+    }
+
+    #[test]
+    fn clones_share_ids() {
+        let first = Interner::new();
+        let second = first.clone();
+
+        let abc = first.intern(br#""abc""#).unwrap();
+        let def = second.intern(br#""def""#).unwrap();
+
+        assert_ne!(abc, def);
+        assert_eq!(second.intern(br#""abc""#).unwrap(), abc);
+    }
+
+    #[test]
+    fn lookup_round_trips() {
+        let interner = Interner::new();
+
+        let abc = interner.intern(br#""abc""#).unwrap();
+        let quote = interner.intern(br#""a\"b""#).unwrap();
+        let big = interner.intern(b"9223372036854775808").unwrap();
+        let one = interner.intern(br#""1""#).unwrap();
+        let two = interner.intern(b"2").unwrap();
+
+        assert_eq!(interner.lookup(abc), Some(RawId::String("abc".into())));
+        assert_eq!(interner.lookup(abc).unwrap().to_string(), r#""abc""#);
+        assert_eq!(interner.lookup(quote).unwrap().to_string(), r#""a\"b""#);
+        assert_eq!(
+            interner.lookup(big),
+            Some(RawId::String("9223372036854775808".into()))
+        );
+        assert_eq!(interner.lookup(one), Some(RawId::String("1".into())));
+        assert_eq!(interner.lookup(one).unwrap().to_string(), r#""1""#);
+        assert_eq!(interner.lookup(two), Some(RawId::Number(2)));
+        assert_eq!(interner.intern(br#""\u0061bc""#).unwrap(), abc);
+        assert_eq!(interner.intern(br#""\u0033""#).unwrap(), 3);
+        assert_eq!(interner.lookup(3), Some(RawId::String("3".into())));
+        assert_eq!(interner.lookup(INTERNED_ID_BASE + 100), None);
+    }
+
+    #[test]
+    fn concurrent_interning_agrees() {
+        let interner = Interner::new();
+        let values: Vec<Vec<u8>> = (0..1000)
+            .map(|i| format!(r#""id-{}""#, i).into_bytes())
+            .collect();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let interner = interner.clone();
+                let values = values.clone();
+                std::thread::spawn(move || {
+                    values
+                        .iter()
+                        .map(|value| interner.intern(value).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let results: Vec<Vec<u64>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        for ids in &results[1..] {
+            assert_eq!(ids, &results[0]);
+        }
+
+        let unique: HashSet<_> = results[0].iter().collect();
+        assert_eq!(unique.len(), values.len());
+
+        for (value, id) in values.iter().zip(&results[0]) {
+            assert_eq!(
+                interner.lookup(*id).unwrap().to_string().as_bytes(),
+                &value[..]
+            );
+        }
+    }
 }
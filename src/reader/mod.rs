@@ -1,4 +1,12 @@
+pub mod compression;
 mod deserialize;
 pub mod interner;
+pub mod options;
+pub mod progress;
 pub mod reader;
-mod types;
+pub mod recovery;
+pub mod types;
+
+pub use deserialize::{
+    deserialize_element, deserialize_element_with, Fields, PayloadDeserializer, Registry,
+};
@@ -0,0 +1,141 @@
+use super::deserialize::Registry;
+use super::interner::Interner;
+use super::progress::{Observer, Progress};
+use super::recovery::ErrorPolicy;
+
+use std::sync::Arc;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// How a reader deserializes a dump. Built up from the defaults with the setters:
+///
+/// ```no_run
+/// # use lsif_protocol::reader::options::ReaderOptions;
+/// # use lsif_protocol::reader::recovery::ErrorPolicy;
+/// let options = ReaderOptions::new()
+///     .workers(2)
+///     .error_policy(ErrorPolicy::Collect);
+/// ```
+#[derive(Clone)]
+pub struct ReaderOptions {
+    pub(crate) workers: usize,
+    pub(crate) line_buffer_size: usize,
+    pub(crate) chunk_size: usize,
+    pub(crate) results_buffer_size: usize,
+    pub(crate) pool: Option<Arc<ThreadPool>>,
+    pub(crate) interner: Interner,
+    pub(crate) registry: Registry,
+    pub(crate) policy: ErrorPolicy,
+    pub(crate) observer: Option<Observer>,
+    pub(crate) progress_interval: u64,
+}
+
+impl ReaderOptions {
+    /// One worker per CPU, batches of 64 lines, chunks of 1MiB and a results buffer of 512
+    /// elements, on a thread pool of the reader's own, reporting progress every 10000
+    /// lines.
+    pub fn new() -> ReaderOptions {
+        let cpus = num_cpus::get();
+
+        ReaderOptions {
+            workers: cpus,
+            line_buffer_size: 64,
+            chunk_size: 1 << 20,
+            results_buffer_size: 512,
+            pool: None,
+            interner: Interner::new(),
+            registry: Registry::new(),
+            policy: ErrorPolicy::default(),
+            observer: None,
+            progress_interval: 10_000,
+        }
+    }
+
+    /// The number of batches of lines, or chunks of a file, handed to the pool at once. Each
+    /// is a short job of its own, so a caller-supplied pool is free for other work between
+    /// them rather than held until the dump is read.
+    pub fn workers(mut self, workers: usize) -> ReaderOptions {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// The number of lines `read_async` and `AsyncReader` hand to the pool in one job. At
+    /// most `workers` such batches are read ahead of the one being passed on.
+    pub fn line_buffer_size(mut self, size: usize) -> ReaderOptions {
+        self.line_buffer_size = size.max(1);
+        self
+    }
+
+    /// The number of bytes `read_file` hands to the pool in one job, extended to the end of
+    /// the line it stops in.
+    pub fn chunk_size(mut self, bytes: usize) -> ReaderOptions {
+        self.chunk_size = bytes.max(1);
+        self
+    }
+
+    /// The number of elements deserialized ahead of the consumer before reading pauses.
+    pub fn results_buffer_size(mut self, size: usize) -> ReaderOptions {
+        self.results_buffer_size = size.max(1);
+        self
+    }
+
+    /// Deserializes on `pool` instead of a pool of `workers` threads created per reader,
+    /// so the reader can share CPUs with the rest of the process. Without one,
+    /// `AsyncReader` uses rayon's global pool rather than a pool of its own.
+    pub fn pool(mut self, pool: Arc<ThreadPool>) -> ReaderOptions {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Interns ids with `interner`, which can then map element ids back to the ids in the
+    /// input.
+    pub fn interner(mut self, interner: Interner) -> ReaderOptions {
+        self.interner = interner;
+        self
+    }
+
+    /// Consults `registry` before the built-in deserializers.
+    pub fn registry(mut self, registry: Registry) -> ReaderOptions {
+        self.registry = registry;
+        self
+    }
+
+    /// Handles lines that fail to deserialize as `policy` says.
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> ReaderOptions {
+        self.policy = policy;
+        self
+    }
+
+    /// Calls `observer` with the reader's progress every `progress_interval` lines and once
+    /// more when it stops. Progress is logged at debug level either way.
+    pub fn on_progress<F>(mut self, observer: F) -> ReaderOptions
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    pub fn progress_interval(mut self, lines: u64) -> ReaderOptions {
+        self.progress_interval = lines.max(1);
+        self
+    }
+
+    pub(crate) fn thread_pool(&self) -> Arc<ThreadPool> {
+        match &self.pool {
+            Some(pool) => pool.clone(),
+            None => Arc::new(
+                ThreadPoolBuilder::new()
+                    .num_threads(self.workers)
+                    .build()
+                    .unwrap(),
+            ),
+        }
+    }
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions::new()
+    }
+}
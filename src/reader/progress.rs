@@ -0,0 +1,155 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Called with the progress of a reader every `progress_interval` lines and once more when
+/// it stops.
+pub type Observer = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// How far a reader has got through a dump.
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    /// Bytes read so far, blank lines included.
+    pub bytes: u64,
+    /// Lines read so far, including blank ones and the ones that failed, so this is the
+    /// number of the last line read.
+    pub lines: u64,
+    /// Elements passed on so far.
+    pub elements: u64,
+    /// Lines that failed to deserialize so far.
+    pub errors: u64,
+    /// Time since the reader was created.
+    pub elapsed: Duration,
+    /// Whether the reader has stopped, i.e. this is the last report.
+    pub done: bool,
+}
+
+impl Progress {
+    pub fn elements_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.elements as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Formats as e.g. `1200 lines (96000 bytes), 1198 elements, 2 errors in 0.52s (2304 elements/s)`.
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lines ({} bytes), {} elements, {} errors in {:.2}s ({:.0} elements/s)",
+            self.lines,
+            self.bytes,
+            self.elements,
+            self.errors,
+            self.elapsed.as_secs_f64(),
+            self.elements_per_second()
+        )
+    }
+}
+
+/// Counts the lines going past and reports to the observer and the log.
+pub(crate) struct Tracker {
+    observer: Option<Observer>,
+    interval: u64,
+    start: Instant,
+    progress: Progress,
+}
+
+impl Tracker {
+    pub(crate) fn new(observer: Option<Observer>, interval: u64) -> Tracker {
+        Tracker {
+            observer,
+            interval,
+            start: Instant::now(),
+            progress: Progress::default(),
+        }
+    }
+
+    /// Records a line of `bytes` bytes that was deserialized into an element or failed.
+    pub(crate) fn record(&mut self, bytes: u64, failed: bool) {
+        if failed {
+            self.progress.errors += 1;
+        } else {
+            self.progress.elements += 1;
+        }
+        self.count_line(bytes);
+    }
+
+    /// Records a blank line of `bytes` bytes, which readers skip.
+    pub(crate) fn skip_blank(&mut self, bytes: u64) {
+        self.count_line(bytes);
+    }
+
+    fn count_line(&mut self, bytes: u64) {
+        self.progress.bytes += bytes;
+        self.progress.lines += 1;
+
+        // `is_multiple_of` would need Rust 1.87
+        #[allow(clippy::manual_is_multiple_of)]
+        if self.progress.lines % self.interval == 0 {
+            self.report();
+        }
+    }
+
+    /// Sends the last report. Later calls do nothing.
+    pub(crate) fn finish(&mut self) {
+        if self.progress.done {
+            return;
+        }
+        self.progress.done = true;
+        self.report();
+    }
+
+    fn report(&mut self) {
+        self.progress.elapsed = self.start.elapsed();
+
+        if self.progress.done {
+            log::info!("finished reading: {}", self.progress);
+        } else {
+            log::debug!("reading: {}", self.progress);
+        }
+
+        if let Some(observer) = &self.observer {
+            observer(&self.progress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    #[test]
+    fn reports_every_interval_and_at_the_end() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let observer: Observer = {
+            let reports = reports.clone();
+            Arc::new(move |progress: &Progress| reports.lock().unwrap().push(progress.clone()))
+        };
+
+        let mut tracker = Tracker::new(Some(observer), 2);
+        tracker.record(10, false);
+        tracker.record(20, true);
+        tracker.skip_blank(1);
+        tracker.record(30, false);
+        tracker.finish();
+        tracker.finish();
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 3);
+
+        assert_eq!((reports[0].lines, reports[0].bytes), (2, 30));
+        assert!(!reports[0].done);
+
+        // blank lines count towards the interval too
+        assert_eq!((reports[1].lines, reports[1].bytes), (4, 61));
+        assert!(!reports[1].done);
+
+        assert_eq!((reports[2].elements, reports[2].errors), (2, 1));
+        assert!(reports[2].done);
+    }
+}
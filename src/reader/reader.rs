@@ -1,186 +1,1201 @@
-use super::deserialize::deserialize_element;
+use super::compression::{decompress, decompress_async, AsyncDecoder, Compression, Decoder};
+use super::deserialize::{deserialize_element_with, Registry};
 use super::interner::Interner;
+use super::options::ReaderOptions;
+use super::recovery::{Recovery, Report, Summary};
 use super::types::*;
 
-use std::io::BufRead;
-use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
 use crossbeam_channel::{bounded, Receiver, Sender};
 
-use lazy_static::lazy_static;
+use futures::channel::oneshot;
+use futures::io::AsyncBufRead;
+use futures::stream::Stream;
 
-use rayon::ThreadPoolBuilder;
+use memmap2::Mmap;
 
-use num_cpus::get;
+use rayon::ThreadPool;
 
-lazy_static! {
-    static ref LINE_BUFFER_SIZE: usize = get() * get(); //(1 as u8).pow(2) as usize;
-    static ref WORKER_COUNT: usize = get();
+/// A line of the dump, or the error that ended reading, along with the offset of its first
+/// byte.
+struct Line {
+    offset: u64,
+    bytes: std::io::Result<Vec<u8>>,
 }
 
-static RESULTS_BUFFER_SIZE: usize = 512;
+/// Whether `line` holds nothing but whitespace, like the trailing newline many tools end a
+/// dump with. Such lines are skipped rather than read as elements.
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
 
-pub fn read_async(r: Box<dyn BufRead + Send>) -> Receiver<Result<Element>> {
-    let (element_sender, element_reciever) = bounded(RESULTS_BUFFER_SIZE);
+/// Reads the dump one line at a time on the calling thread, for callers that don't need a
+/// thread pool:
+///
+/// ```no_run
+/// # use lsif_protocol::reader::reader::Reader;
+/// # let file = std::io::BufReader::new(std::fs::File::open("dump.lsif").unwrap());
+/// for element in Reader::new(file) {
+///     let element = element.unwrap();
+/// }
+/// ```
+///
+/// Compressed dumps and errors are handled as `read_async` handles them.
+pub struct Reader<R> {
+    /// The input, or the error telling its encoding failed with.
+    r: io::Result<Decoder<R>>,
+    interner: Interner,
+    registry: Registry,
+    recovery: Recovery,
+    line: Vec<u8>,
+    number: u64,
+    offset: u64,
+    done: bool,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(r: R) -> Reader<R> {
+        Reader::with(r, ReaderOptions::default())
+    }
+
+    /// A reader using the interner, registry and error policy of `options`. The other
+    /// options don't apply, as everything happens on the calling thread. The first few bytes
+    /// of `r` are read right away, to tell whether the dump is compressed.
+    pub fn with(r: R, options: ReaderOptions) -> Reader<R> {
+        Reader {
+            r: decompress(r),
+            recovery: Recovery::new(&options),
+            interner: options.interner,
+            registry: options.registry,
+            line: Vec::new(),
+            number: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// The interner the ids read so far went into.
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    /// An account of the lines read so far.
+    pub fn summary(&self) -> Summary {
+        self.recovery.report().summary()
+    }
+
+    fn read_line(&mut self) -> Option<Result<Element>> {
+        loop {
+            self.line.clear();
+            self.number += 1;
+
+            let read = match &mut self.r {
+                Ok(r) => r.read_until(b'\n', &mut self.line),
+                // an IO error always stops the reader, so this is only reported once
+                Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            };
+
+            let result = match read {
+                Ok(0) => return None,
+                Ok(_) if is_blank(&self.line) => {
+                    self.offset += self.line.len() as u64;
+                    self.recovery.skip_blank(self.line.len() as u64);
+                    continue;
+                }
+                Ok(_) => deserialize_element_with(&self.registry, &self.interner, &self.line),
+                Err(err) => Err(ProtocolError::new(ErrorKind::IO(err.to_string()))),
+            };
 
-    let interner = Interner::new();
+            let result = result.map_err(|err| err.at_line(self.number, self.offset));
+            self.offset += self.line.len() as u64;
+            return Some(result);
+        }
+    }
+}
 
-    read_lines(interner, r, element_sender);
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Element>;
 
-    element_reciever
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let result = match self.read_line() {
+                Some(result) => result,
+                None => break,
+            };
+
+            if let Some(result) = self.recovery.apply(result, self.line.len() as u64) {
+                if result.is_err() {
+                    self.done = true;
+                    self.recovery.finish();
+                }
+                return Some(result);
+            }
+        }
+
+        self.done = true;
+        self.recovery.finish();
+        None
+    }
 }
 
-fn read_lines(
+/// Reads the dump from an async reader as a `Stream`, so async callers don't need to block
+/// a thread on `read_async`'s receiver. Lines are read as the stream is polled and handed
+/// to the pool in batches of `line_buffer_size`, with at most `workers` batches in flight:
+/// reading pauses until the consumer catches up. Elements come out in input order.
+///
+/// No threads are started for the reader. Batches run on the pool given with
+/// `ReaderOptions::pool`, or on rayon's global pool, so services reading many dumps at
+/// once share one set of threads either way.
+///
+/// See `read_async` for compressed dumps and the default error policy.
+pub struct AsyncReader<R> {
+    r: AsyncDecoder<R>,
     interner: Interner,
-    mut r: Box<dyn BufRead + Send>,
-    element_sender: Sender<Result<Element>>,
-) {
-    let (line_send, line_recv) = bounded::<(u64, Vec<u8>)>(*LINE_BUFFER_SIZE);
-    let (results_send, results_recv) = bounded::<(u64, Result<Element>)>(*LINE_BUFFER_SIZE);
-
-    let signal = Arc::new((Mutex::new(false), Condvar::new()));
-
-    let pool = ThreadPoolBuilder::new()
-        .num_threads(*WORKER_COUNT)
-        .build()
-        .unwrap();
-
-    let reader_done = Arc::new(RwLock::new(false));
-    let worker_done = Arc::new(RwLock::new(false));
-
-    {
-        let reader_done = reader_done.clone();
-        // file reader thread
-        std::thread::spawn(move || {
-            let mut idx = 0 as u64;
-            loop {
-                let mut line = Vec::new();
-                match r.read_until(b'\n', &mut line) {
-                    Ok(_) => {
-                        if line.is_empty() {
-                            println!("done reading");
-                            *reader_done.write().unwrap() = true;
-                            return;
-                        }
-                        line_send.send((idx, line)).unwrap();
-                    }
-                    Err(_) => {
-                        println!("done reading");
-                        *reader_done.write().unwrap() = true;
-                        return;
+    registry: Arc<Registry>,
+    recovery: Recovery,
+    pool: Option<Arc<ThreadPool>>,
+    workers: usize,
+    line_buffer_size: usize,
+    /// The start of a line whose end hasn't been read yet.
+    line: Vec<u8>,
+    /// The lines read since the last batch was handed to the pool.
+    batch: Vec<Line>,
+    number: u64,
+    offset: u64,
+    /// The batches being deserialized, in input order.
+    in_flight: VecDeque<oneshot::Receiver<Batch>>,
+    /// The results of the oldest finished batch that haven't been passed on yet.
+    ready: VecDeque<(u64, Option<Result<Element>>)>,
+    lines_before: u64,
+    eof: bool,
+    done: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncReader<R> {
+    pub fn new(r: R) -> AsyncReader<R> {
+        AsyncReader::with(r, ReaderOptions::default())
+    }
+
+    /// A reader configured by `options`. The results buffer size doesn't apply, as results
+    /// wait in their batches until the stream is polled.
+    pub fn with(r: R, options: ReaderOptions) -> AsyncReader<R> {
+        AsyncReader {
+            r: decompress_async(r),
+            recovery: Recovery::new(&options),
+            pool: options.pool,
+            workers: options.workers,
+            line_buffer_size: options.line_buffer_size,
+            interner: options.interner,
+            registry: Arc::new(options.registry),
+            line: Vec::new(),
+            batch: Vec::new(),
+            number: 0,
+            offset: 0,
+            in_flight: VecDeque::new(),
+            ready: VecDeque::new(),
+            lines_before: 0,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// For looking up the input ids of the elements streamed so far.
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    /// An account of the lines passed on so far.
+    pub fn summary(&self) -> Summary {
+        self.recovery.report().summary()
+    }
+
+    /// Reads lines into batches and hands them to the pool until `workers` are in flight,
+    /// the input ends or the reader has nothing ready. A partial batch is handed on when
+    /// nothing else is in flight, rather than waiting for the reader to fill it.
+    fn read_ahead(&mut self, cx: &mut Context<'_>) {
+        while !self.eof && self.in_flight.len() < self.workers {
+            let offset = self.offset;
+            match self.poll_line(cx) {
+                Poll::Pending => {
+                    if self.in_flight.is_empty() {
+                        self.spawn_batch();
                     }
+                    return;
+                }
+                Poll::Ready(Ok(None)) => self.eof = true,
+                Poll::Ready(Ok(Some(bytes))) => {
+                    self.number += 1;
+                    self.offset += bytes.len() as u64;
+                    self.batch.push(Line {
+                        offset,
+                        bytes: Ok(bytes),
+                    });
+                }
+                Poll::Ready(Err(err)) => {
+                    self.number += 1;
+                    self.eof = true;
+                    self.batch.push(Line {
+                        offset,
+                        bytes: Err(err),
+                    });
                 }
-                idx = (idx + 1) % *WORKER_COUNT as u64;
             }
-        });
-    }
-
-    {
-        let worker_done = worker_done.clone();
-        let signal = signal.clone();
-        std::thread::spawn(move || {
-            let (lock, sigvar) = &*signal;
-            while !*reader_done.read().unwrap() {
-                let mut ready = lock.lock().unwrap();
-
-                pool.scope(|s| {
-                    for _ in 0..*WORKER_COUNT {
-                        let interner = interner.clone();
-                        let line_recv = line_recv.clone();
-                        let results_send = results_send.clone();
-
-                        s.spawn(move |_| {
-                            let (idx, line) = match line_recv.recv() {
-                                Ok(line_pair) => line_pair,
-                                Err(_) => return,
-                            };
-
-                            let element = deserialize_element(&interner, &line);
-                            println!("sending a result");
-                            results_send.send((idx, element)).unwrap();
-                        });
-                    }
-                });
 
-                // set and signal aggregator
-                *ready = true;
-                sigvar.notify_one();
+            if self.eof || self.batch.len() >= self.line_buffer_size {
+                self.spawn_batch();
+            }
+        }
+    }
+
+    fn spawn_batch(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let lines = std::mem::take(&mut self.batch);
+        let interner = self.interner.clone();
+        let registry = self.registry.clone();
+        let (send, recv) = oneshot::channel();
+        let job = move || {
+            let _ = send.send(deserialize_lines(lines, &interner, &registry));
+        };
+
+        match &self.pool {
+            Some(pool) => pool.spawn(job),
+            None => rayon::spawn(job),
+        }
+        self.in_flight.push_back(recv);
+    }
 
-                // wait for signal from aggregator
-                let mut ready = lock.lock().unwrap();
-                while *ready {
-                    ready = sigvar.wait(ready).unwrap();
+    /// Reads up to and including the next newline. Returns `None` at the end of input.
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<Option<Vec<u8>>>> {
+        loop {
+            let buf = match Pin::new(&mut self.r).poll_fill_buf(cx) {
+                Poll::Ready(Ok(buf)) => buf,
+                Poll::Ready(Err(err)) => {
+                    self.line.clear();
+                    return Poll::Ready(Err(err));
                 }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if buf.is_empty() {
+                let line = std::mem::take(&mut self.line);
+                return Poll::Ready(Ok(Some(line).filter(|line| !line.is_empty())));
             }
-            println!("worker done");
-            *worker_done.write().unwrap() = true;
-        });
+
+            match buf.iter().position(|b| *b == b'\n') {
+                Some(at) => {
+                    self.line.extend_from_slice(&buf[..=at]);
+                    Pin::new(&mut self.r).consume(at + 1);
+                    return Poll::Ready(Ok(Some(std::mem::take(&mut self.line))));
+                }
+                None => {
+                    let len = buf.len();
+                    self.line.extend_from_slice(buf);
+                    Pin::new(&mut self.r).consume(len);
+                }
+            }
+        }
     }
+}
 
-    {
-        std::thread::spawn(move || {
-            let mut elements = Vec::<Result<Element>>::with_capacity(*WORKER_COUNT);
+impl<R: AsyncBufRead + Unpin> Stream for AsyncReader<R> {
+    type Item = Result<Element>;
 
-            let (lock, sigvar) = &*signal;
-            while !*worker_done.read().unwrap() {
-                // wait for signal from worker manager
-                let mut ready = lock.lock().unwrap();
-                while !*ready {
-                    ready = sigvar.wait(ready).unwrap();
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while !this.done {
+            if let Some((len, result)) = this.ready.pop_front() {
+                let result = match result {
+                    Some(result) => this.recovery.apply(result, len),
+                    None => {
+                        this.recovery.skip_blank(len);
+                        None
+                    }
+                };
+                if let Some(result) = result {
+                    if result.is_err() {
+                        this.done = true;
+                        this.recovery.finish();
+                    }
+                    return Poll::Ready(Some(result));
                 }
+                continue;
+            }
 
-                for _ in 0..*WORKER_COUNT {
-                    let el = match results_recv.recv() {
-                        Ok(el) => el,
-                        Err(_) => return,
-                    };
+            this.read_ahead(cx);
 
-                    println!("got a result");
-                    elements[el.0 as usize] = el.1;
+            let batch = match this.in_flight.front_mut() {
+                Some(recv) => match Pin::new(recv).poll(cx) {
+                    Poll::Ready(batch) => {
+                        this.in_flight.pop_front();
+                        batch.unwrap_or_else(|_| worker_stopped())
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                None if this.eof => break,
+                None => return Poll::Pending,
+            };
+
+            let lines_before = this.lines_before;
+            this.lines_before += batch.len() as u64;
+            this.ready.extend(
+                batch
+                    .into_iter()
+                    .map(|(len, result)| (len, renumber(result, lines_before))),
+            );
+        }
+
+        this.done = true;
+        this.recovery.finish();
+        Poll::Ready(None)
+    }
+}
+
+/// Reads the dump in parallel, stopping at the first line that fails to deserialize.
+/// Compressed dumps are decoded on the fly, see `compression::decompress`.
+pub fn read_async(r: Box<dyn BufRead + Send>) -> Receiver<Result<Element>> {
+    read_async_with(r, ReaderOptions::default()).0
+}
+
+/// Like `read_async`, but configured by `options`. The returned `Report` summarizes the
+/// lines read once the receiver is drained.
+pub fn read_async_with(
+    r: Box<dyn BufRead + Send>,
+    options: ReaderOptions,
+) -> (Receiver<Result<Element>>, Report) {
+    let (element_sender, element_reciever) = bounded(options.results_buffer_size);
+
+    let recovery = Recovery::new(&options);
+    let report = recovery.report();
+
+    read_lines(&options, recovery, r, element_sender);
+
+    (element_reciever, report)
+}
+
+/// Lines are read in batches of `line_buffer_size` on a thread of the reader's own, which
+/// hands each batch to the pool as a job of its own and passes the results on in input
+/// order. See `deserialize_in_order`.
+fn read_lines(
+    options: &ReaderOptions,
+    recovery: Recovery,
+    r: Box<dyn BufRead + Send>,
+    element_sender: Sender<Result<Element>>,
+) {
+    let pool = options.thread_pool();
+    let workers = options.workers;
+    let batches = LineBatches {
+        r: Some(r),
+        decompressed: false,
+        batch_size: options.line_buffer_size,
+        offset: 0,
+        interner: options.interner.clone(),
+        registry: Arc::new(options.registry.clone()),
+    };
+
+    std::thread::spawn(move || {
+        deserialize_in_order(&pool, workers, batches, recovery, element_sender)
+    });
+}
+
+/// The results of a batch of lines or a chunk of a mapped dump, each with its length, or
+/// `None` for blank lines. Errors are located at their line within the batch until the
+/// lines before the batch are counted.
+type Batch = Vec<(u64, Option<Result<Element>>)>;
+
+/// Reads the lines of a dump into jobs that deserialize `batch_size` lines at a time. The
+/// input is decompressed when the first batch is read, and reading stops after an error.
+struct LineBatches {
+    r: Option<Box<dyn BufRead + Send>>,
+    decompressed: bool,
+    batch_size: usize,
+    offset: u64,
+    interner: Interner,
+    registry: Arc<Registry>,
+}
+
+impl Iterator for LineBatches {
+    type Item = Box<dyn FnOnce() -> Batch + Send>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut lines = Vec::new();
+
+        if !self.decompressed {
+            self.decompressed = true;
+            match self.r.take().map(decompress) {
+                Some(Ok(r)) => self.r = Some(Box::new(r)),
+                Some(Err(err)) => lines.push(Line {
+                    offset: 0,
+                    bytes: Err(err),
+                }),
+                None => {}
+            }
+        }
+
+        while lines.len() < self.batch_size {
+            let r = match &mut self.r {
+                Some(r) => r,
+                None => break,
+            };
+
+            let mut bytes = Vec::new();
+            let offset = self.offset;
+            match r.read_until(b'\n', &mut bytes) {
+                Ok(0) => self.r = None,
+                Ok(len) => {
+                    self.offset += len as u64;
+                    lines.push(Line {
+                        offset,
+                        bytes: Ok(bytes),
+                    });
+                }
+                Err(err) => {
+                    self.r = None;
+                    lines.push(Line {
+                        offset,
+                        bytes: Err(err),
+                    });
                 }
+            }
+        }
 
-                for i in 0..*WORKER_COUNT {
-                    let el_res = match &elements[i] {
-                        Ok(el) => Ok(el.clone()),
-                        Err(err) => Err(err.clone()),
-                    };
+        if lines.is_empty() {
+            return None;
+        }
+
+        let interner = self.interner.clone();
+        let registry = self.registry.clone();
+        Some(Box::new(move || {
+            deserialize_lines(lines, &interner, &registry)
+        }))
+    }
+}
+
+/// Deserializes a batch of lines. Errors are located at their line within the batch.
+fn deserialize_lines(lines: Vec<Line>, interner: &Interner, registry: &Registry) -> Batch {
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, Line { offset, bytes })| {
+            let len = bytes.as_ref().map_or(0, |bytes| bytes.len() as u64);
+            let element = match bytes {
+                Ok(bytes) if is_blank(&bytes) => None,
+                Ok(bytes) => Some(deserialize_element_with(registry, interner, &bytes)),
+                Err(err) => Some(Err(ProtocolError::new(ErrorKind::IO(err.to_string())))),
+            };
+            let element =
+                element.map(|element| element.map_err(|err| err.at_line(i as u64 + 1, offset)));
+            (len, element)
+        })
+        .collect()
+}
+
+/// Moves an error located within a batch to its line within the dump.
+fn renumber(result: Option<Result<Element>>, lines_before: u64) -> Option<Result<Element>> {
+    result.map(|result| {
+        result.map_err(|mut err| {
+            err.location.line = err.location.line.map(|line| line + lines_before);
+            err
+        })
+    })
+}
+
+/// The batch standing in for one whose job was dropped before it finished.
+fn worker_stopped() -> Batch {
+    vec![(
+        0,
+        Some(Err(ProtocolError::new(ErrorKind::Other(
+            "the worker deserializing the line stopped".into(),
+        ))
+        .at_line(1, 0))),
+    )]
+}
+
+/// Hands the jobs of `batches` to the pool, at most `max_in_flight` at a time, and passes
+/// their results on in input order. Jobs never wait on anything, so the pool stays free
+/// for other work between them; it is this thread that waits, on the oldest job and on
+/// the consumer. This stops once the consumer hangs up or an error is passed on, and the
+/// receiver is closed once every line has been passed on.
+fn deserialize_in_order<I>(
+    pool: &ThreadPool,
+    max_in_flight: usize,
+    batches: I,
+    mut recovery: Recovery,
+    element_sender: Sender<Result<Element>>,
+) where
+    I: Iterator<Item = Box<dyn FnOnce() -> Batch + Send>>,
+{
+    let mut batches = batches.fuse();
+    let mut in_flight = VecDeque::new();
+    let mut lines_before = 0;
+
+    'batches: loop {
+        while in_flight.len() < max_in_flight {
+            let job = match batches.next() {
+                Some(job) => job,
+                None => break,
+            };
 
-                    element_sender.send(el_res).unwrap();
+            // a single message never fills the channel, so the job can't block on it
+            let (send, recv) = bounded(1);
+            pool.spawn(move || {
+                let _ = send.send(job());
+            });
+            in_flight.push_back(recv);
+        }
+
+        let batch: Batch = match in_flight.pop_front() {
+            Some(recv) => recv.recv().unwrap_or_else(|_| worker_stopped()),
+            None => break,
+        };
+
+        let count = batch.len() as u64;
+        for (len, result) in batch {
+            let result = renumber(result, lines_before);
+
+            let result = match result {
+                Some(result) => recovery.apply(result, len),
+                None => {
+                    recovery.skip_blank(len);
+                    None
                 }
+            };
+            let result = match result {
+                Some(result) => result,
+                None => continue,
+            };
 
-                // reset and signal worker manager
-                *ready = false;
-                sigvar.notify_one();
+            let failed = result.is_err();
+            if element_sender.send(result).is_err() || failed {
+                break 'batches;
             }
-        });
+        }
+        lines_before += count;
+    }
+
+    recovery.finish();
+}
+
+/// Reads the dump at `path` in parallel, stopping at the first line that fails to
+/// deserialize. Unlike `read_async`, which copies each line off a single reading thread,
+/// the file is memory-mapped and split into newline-aligned chunks that the workers
+/// deserialize in place, which pays off for dumps of several GBs.
+///
+/// Compressed dumps can't be split, so they are decoded and read like `read_async` does.
+/// The file must not be truncated while it is being read.
+pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<Receiver<Result<Element>>> {
+    read_file_with(path, ReaderOptions::default()).map(|(chan, _)| chan)
+}
+
+/// Like `read_file`, but configured by `options`. The line buffer size doesn't apply, as
+/// the workers take chunks of `chunk_size` bytes instead.
+pub fn read_file_with<P: AsRef<Path>>(
+    path: P,
+    options: ReaderOptions,
+) -> io::Result<(Receiver<Result<Element>>, Report)> {
+    // pipes and the like can't be mapped, and files under /proc map empty whatever they
+    // hold, so these are read as a stream
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(read_async_with(Box::new(BufReader::new(file)), options));
+    }
+
+    // safety: the map is only read, and the caller is told not to truncate the file
+    let map = unsafe { Mmap::map(&file)? };
+    if Compression::sniff(&map) != Compression::None {
+        return Ok(read_async_with(Box::new(BufReader::new(file)), options));
+    }
+
+    let (element_sender, element_reciever) = bounded(options.results_buffer_size);
+
+    let recovery = Recovery::new(&options);
+    let report = recovery.report();
+
+    read_chunks(&options, recovery, Arc::new(map), element_sender);
+
+    Ok((element_reciever, report))
+}
+
+/// Like `read_lines`, with chunks of the map in place of batches of lines: they are split
+/// off on a thread of the reader's own, and deserialized in place by jobs on the pool.
+fn read_chunks(
+    options: &ReaderOptions,
+    recovery: Recovery,
+    map: Arc<Mmap>,
+    element_sender: Sender<Result<Element>>,
+) {
+    let pool = options.thread_pool();
+    let workers = options.workers;
+    let chunks = Chunks {
+        map,
+        start: 0,
+        chunk_size: options.chunk_size,
+        interner: options.interner.clone(),
+        registry: Arc::new(options.registry.clone()),
+    };
+
+    std::thread::spawn(move || {
+        deserialize_in_order(&pool, workers, chunks, recovery, element_sender)
+    });
+}
+
+/// Splits a mapped dump into jobs that deserialize chunks of at least `chunk_size` bytes
+/// that end at the end of a line.
+struct Chunks {
+    map: Arc<Mmap>,
+    start: usize,
+    chunk_size: usize,
+    interner: Interner,
+    registry: Arc<Registry>,
+}
+
+impl Iterator for Chunks {
+    type Item = Box<dyn FnOnce() -> Batch + Send>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.start;
+        if start == self.map.len() {
+            return None;
+        }
+
+        let end = (start + self.chunk_size).min(self.map.len());
+        let end = match self.map[end - 1..].iter().position(|b| *b == b'\n') {
+            Some(at) => end + at,
+            None => self.map.len(),
+        };
+        self.start = end;
+
+        let map = self.map.clone();
+        let interner = self.interner.clone();
+        let registry = self.registry.clone();
+        Some(Box::new(move || {
+            let mut offset = start as u64;
+            map[start..end]
+                .split_inclusive(|b| *b == b'\n')
+                .enumerate()
+                .map(|(i, line)| {
+                    let element = (!is_blank(line)).then(|| {
+                        deserialize_element_with(&registry, &interner, line)
+                            .map_err(|err| err.at_line(i as u64 + 1, offset))
+                    });
+                    offset += line.len() as u64;
+                    (line.len() as u64, element)
+                })
+                .collect()
+        }))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::read_async;
+    use super::*;
+
+    use crate::reader::recovery::ErrorPolicy;
+
+    use std::io::{self, BufReader, Cursor, Read};
+
+    fn lines(count: u64) -> String {
+        (1..=count)
+            .map(|id| format!(r#"{{"id":{},"type":"vertex","label":"resultSet"}}"#, id))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn ids(chan: Receiver<Result<Element>>) -> Vec<u64> {
+        chan.iter().map(|element| element.unwrap().id).collect()
+    }
 
     #[test]
     fn basic() {
-        let string = r#"{ id: 2, type: "vertex", label: "project", kind: "typescript" }
-{ id: 4, type: "vertex", label: "document", uri: "file:///home/burger/sample.ts", languageId: "typescript", contents: "..." }
-{ id: 5, type: "vertex", label: "$event", kind: "begin", scope: "document" , data: 4 }
-{ id: 3, type: "vertex", label: "$event", kind: "begin", scope: "project", data: 2 }
-{ id: 53, type: "vertex", label: "$event", kind: "end", scope: "document", data: 4 }
-{ id: 54, type: "edge", label: "contains", outV: 2, inVs: [4] }
-{ id: 55, type: "vertex", label: "$event", kind: "end", scope: "project", data: 2 }"#;
+        let string = r#"{ "id": 2, "type": "vertex", "label": "project", "kind": "typescript" }
+{ "id": 4, "type": "vertex", "label": "document", "uri": "file:///home/burger/sample.ts", "languageId": "typescript", "contents": "..." }
+{ "id": 5, "type": "vertex", "label": "$event", "kind": "begin", "scope": "document" , "data": 4 }
+{ "id": 3, "type": "vertex", "label": "$event", "kind": "begin", "scope": "project", "data": 2 }
+{ "id": 53, "type": "vertex", "label": "$event", "kind": "end", "scope": "document", "data": 4 }
+{ "id": 54, "type": "edge", "label": "contains", "outV": 2, "inVs": [4] }
+{ "id": 55, "type": "vertex", "label": "$event", "kind": "end", "scope": "project", "data": 2 }"#;
 
         let chan = read_async(Box::new(string.as_bytes()));
 
-        let mut count = 0;
-        for _ in 0..7 {
-            match chan.recv() {
-                Ok(_) => println!("got an el"),
-                Err(_) => break,
-            };
-            count += 1;
+        assert_eq!(ids(chan), vec![2, 4, 5, 3, 53, 54, 55]);
+    }
+
+    #[test]
+    fn shorter_than_worker_count() {
+        let chan = read_async(Box::new("".as_bytes()));
+        assert!(ids(chan).is_empty());
+
+        let chan = read_async(Box::new(Cursor::new(lines(1))));
+        assert_eq!(ids(chan), vec![1]);
+    }
+
+    #[test]
+    fn longer_than_worker_count() {
+        let count = (num_cpus::get() * 100 + 3) as u64;
+
+        let chan = read_async(Box::new(Cursor::new(lines(count))));
+        assert_eq!(ids(chan), (1..=count).collect::<Vec<_>>());
+
+        // more workers than threads, with barely any buffering
+        let options = ReaderOptions::new()
+            .workers(5)
+            .line_buffer_size(1)
+            .results_buffer_size(1)
+            .pool(Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(2)
+                    .build()
+                    .unwrap(),
+            ));
+        let (chan, _) = read_async_with(Box::new(Cursor::new(lines(count))), options);
+        assert_eq!(ids(chan), (1..=count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shared_pool() {
+        use futures::executor::block_on;
+        use futures::stream::StreamExt;
+        use std::time::Duration;
+
+        let pool = Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap(),
+        );
+        let options = || {
+            ReaderOptions::new()
+                .pool(pool.clone())
+                .workers(2)
+                .line_buffer_size(4)
+                .results_buffer_size(1)
+        };
+        let count = 200;
+
+        // readers whose consumers don't keep up
+        let (stalled, _) = read_async_with(Box::new(Cursor::new(lines(count))), options());
+        let file = TempFile::new("shared_pool", lines(count).as_bytes());
+        let (stalled_file, _) = read_file_with(&file.0, options().chunk_size(64)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        // doesn't keep other readers or jobs from the pool
+        let (chan, _) = read_async_with(Box::new(Cursor::new(lines(count))), options());
+        let mut read = Vec::new();
+        while let Ok(element) = chan.recv_timeout(Duration::from_secs(5)) {
+            read.push(element.unwrap().id);
+        }
+        assert_eq!(read, (1..=count).collect::<Vec<_>>());
+
+        let reader = AsyncReader::with(futures::io::Cursor::new(lines(count)), options());
+        assert_eq!(block_on(reader.count()), count as usize);
+
+        let (send, recv) = bounded(1);
+        pool.spawn(move || send.send(()).unwrap());
+        assert!(recv.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        assert_eq!(ids(stalled), (1..=count).collect::<Vec<_>>());
+        assert_eq!(ids(stalled_file), (1..=count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn blank_lines() {
+        use crate::reader::progress::Progress;
+        use futures::executor::block_on;
+        use futures::stream::StreamExt;
+        use std::sync::Mutex;
+
+        let string = format!("\n{}\n\n  \nnot json\n{}\n\n", lines(2), lines(1));
+        let progress = Arc::new(Mutex::new(Progress::default()));
+        let options = || {
+            let progress = progress.clone();
+            ReaderOptions::new()
+                .error_policy(ErrorPolicy::Collect)
+                .on_progress(move |p| *progress.lock().unwrap() = p.clone())
+        };
+        // blank lines are counted like any other, so the totals match the input
+        let assert_counted = |summary: Summary| {
+            assert_eq!(summary.lines, 8);
+            let progress = progress.lock().unwrap();
+            assert_eq!((progress.lines, progress.bytes), (8, string.len() as u64));
+        };
+
+        let (chan, report) = read_async_with(Box::new(Cursor::new(string.clone())), options());
+        assert_eq!(ids(chan), vec![1, 2, 1]);
+        assert_eq!(report.summary().skipped, vec![6]);
+        assert_counted(report.summary());
+
+        let mut reader = Reader::with(Cursor::new(string.clone()), options());
+        let sync: Vec<_> = reader.by_ref().map(|element| element.unwrap().id).collect();
+        assert_eq!(sync, vec![1, 2, 1]);
+        assert_eq!(reader.summary().skipped, vec![6]);
+        assert_counted(reader.summary());
+
+        let mut reader = AsyncReader::with(futures::io::Cursor::new(string.clone()), options());
+        let streamed: Vec<_> = block_on(
+            reader
+                .by_ref()
+                .map(|element| element.unwrap().id)
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(streamed, vec![1, 2, 1]);
+        assert_eq!(reader.summary().skipped, vec![6]);
+        assert_counted(reader.summary());
+
+        let file = TempFile::new("blank_lines", string.as_bytes());
+        let (chan, report) = read_file_with(&file.0, options().chunk_size(8)).unwrap();
+        assert_eq!(ids(chan), vec![1, 2, 1]);
+        assert_eq!(report.summary().skipped, vec![6]);
+        assert_counted(report.summary());
+
+        // trailing blank lines don't stop a read under the default policy
+        let chan = read_async(Box::new(Cursor::new(format!("{}\n\n", lines(2)))));
+        assert_eq!(ids(chan), vec![1, 2]);
+    }
+
+    #[test]
+    fn errors_keep_their_place() {
+        let string = format!("{}\nnot json\n{}", lines(2), lines(1));
+
+        let results: Vec<_> = read_async(Box::new(Cursor::new(string.clone())))
+            .iter()
+            .collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2].as_ref().err().unwrap().location.line, Some(3));
+
+        let (chan, report) = read_async_with(
+            Box::new(Cursor::new(string)),
+            ReaderOptions::new().error_policy(ErrorPolicy::Collect),
+        );
+        assert_eq!(ids(chan), vec![1, 2, 1]);
+        assert_eq!(report.summary().skipped, vec![3]);
+    }
+
+    /// Fails once `remaining` bytes have been read.
+    struct FailingReader {
+        remaining: usize,
+        inner: Cursor<String>,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(io::Error::other("disk on fire"));
+            }
+            let len = buf.len().min(self.remaining);
+            let read = self.inner.read(&mut buf[..len])?;
+            self.remaining -= read;
+            Ok(read)
         }
+    }
+
+    #[test]
+    fn progress() {
+        use crate::reader::progress::Progress;
+        use std::sync::Mutex;
+
+        let string = format!("{}\nnot json\n{}", lines(20), lines(4));
+        let reports = Arc::new(Mutex::new(Vec::<Progress>::new()));
+
+        let options = {
+            let reports = reports.clone();
+            ReaderOptions::new()
+                .error_policy(ErrorPolicy::Collect)
+                .progress_interval(10)
+                .on_progress(move |progress| reports.lock().unwrap().push(progress.clone()))
+        };
+        let (chan, _) = read_async_with(Box::new(Cursor::new(string.clone())), options);
+        assert_eq!(ids(chan).len(), 24);
+
+        let reports = reports.lock().unwrap();
+        let lines: Vec<_> = reports.iter().map(|progress| progress.lines).collect();
+        assert_eq!(lines, vec![10, 20, 25]);
+
+        let last = reports.last().unwrap();
+        assert!(last.done);
+        assert_eq!(last.bytes, string.len() as u64);
+        assert_eq!((last.elements, last.errors), (24, 1));
+    }
+
+    #[test]
+    fn sync_reader() {
+        let count = 103;
+
+        let elements: Vec<_> = Reader::new(Cursor::new(lines(count)))
+            .map(|element| element.unwrap().id)
+            .collect();
+        assert_eq!(elements, (1..=count).collect::<Vec<_>>());
+
+        assert_eq!(Reader::new(Cursor::new("")).count(), 0);
+
+        let string = format!("{}\nnot json\n{}", lines(2), lines(1));
+        let results: Vec<_> = Reader::new(Cursor::new(string.clone())).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2].as_ref().err().unwrap().location.line, Some(3));
+        let start = string.find("not").unwrap() as u64;
+        let offset = results[2].as_ref().err().unwrap().location.offset.unwrap();
+        assert!((start..start + 8).contains(&offset));
+
+        let mut reader = Reader::with(
+            Cursor::new(string),
+            ReaderOptions::new().error_policy(ErrorPolicy::Collect),
+        );
+        assert_eq!(
+            reader.by_ref().map(|e| e.unwrap().id).collect::<Vec<_>>(),
+            vec![1, 2, 1]
+        );
+        assert_eq!(reader.summary().skipped, vec![3]);
+        assert_eq!(reader.summary().lines, 4);
+    }
+
+    #[test]
+    fn async_reader() {
+        use futures::executor::block_on;
+        use futures::stream::StreamExt;
+
+        let count = 1027;
+
+        let mut reader = AsyncReader::new(futures::io::Cursor::new(lines(count)));
+        let first = block_on(reader.next()).unwrap().unwrap();
+        assert_eq!(first.id, 1);
+
+        // nothing is read beyond the in flight batches until the consumer catches up
+        let mut reader = AsyncReader::with(
+            futures::io::Cursor::new(lines(count)),
+            ReaderOptions::new().workers(2).line_buffer_size(4),
+        );
+        block_on(reader.next()).unwrap().unwrap();
+        assert_eq!(reader.number, 2 * 4);
+
+        let rest: Vec<_> = block_on(reader.map(|element| element.unwrap().id).collect());
+        assert_eq!(rest, (2..=count).collect::<Vec<_>>());
+
+        let reader = AsyncReader::new(futures::io::Cursor::new(""));
+        assert_eq!(block_on(reader.count()), 0);
+
+        // a line is passed on without waiting for the rest of its batch to arrive
+        let (send, recv) = futures::channel::mpsc::unbounded::<std::io::Result<Vec<u8>>>();
+        send.unbounded_send(Ok(format!("{}\n", lines(1)).into_bytes()))
+            .unwrap();
+        let mut reader = AsyncReader::with(
+            futures::stream::TryStreamExt::into_async_read(recv),
+            ReaderOptions::new().line_buffer_size(64),
+        );
+        assert_eq!(block_on(reader.next()).unwrap().unwrap().id, 1);
+        send.unbounded_send(Ok(lines(2).into_bytes())).unwrap();
+        drop(send);
+        let rest: Vec<_> = block_on(reader.map(|element| element.unwrap().id).collect());
+        assert_eq!(rest, vec![1, 2]);
+
+        let string = format!("{}\nnot json\n{}", lines(2), lines(1));
+        let results: Vec<_> = block_on(
+            AsyncReader::new(futures::io::Cursor::new(string.clone())).collect::<Vec<_>>(),
+        );
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2].as_ref().err().unwrap().location.line, Some(3));
+
+        let mut reader = AsyncReader::with(
+            futures::io::Cursor::new(string),
+            ReaderOptions::new().error_policy(ErrorPolicy::Collect),
+        );
+        let ids: Vec<_> = block_on(
+            reader
+                .by_ref()
+                .map(|element| element.unwrap().id)
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(ids, vec![1, 2, 1]);
+        assert_eq!(reader.summary().skipped, vec![3]);
+    }
+
+    #[test]
+    fn io_errors() {
+        let string = lines(10);
+        let remaining = string.lines().take(3).map(|line| line.len() + 1).sum();
+
+        let (chan, report) = read_async_with(
+            Box::new(BufReader::with_capacity(
+                8,
+                FailingReader {
+                    remaining,
+                    inner: Cursor::new(string),
+                },
+            )),
+            ReaderOptions::new().error_policy(ErrorPolicy::Collect),
+        );
+        let results: Vec<_> = chan.iter().collect();
+
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|result| result.is_ok()));
+
+        let err = results[3].as_ref().err().unwrap();
+        assert!(matches!(err.kind, ErrorKind::IO(_)));
+        assert_eq!(err.location.line, Some(4));
+        assert_eq!(err.location.offset, Some(remaining as u64));
+        assert!(report.summary().aborted.is_some());
+    }
+
+    /// Writes `contents` to a file of its own, removed once dropped.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("{}-{}.lsif", name, std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn read_file_in_chunks() {
+        let count = 1000;
+        let file = TempFile::new("read_file_in_chunks", lines(count).as_bytes());
+
+        let chan = read_file(&file.0).unwrap();
+        assert_eq!(ids(chan), (1..=count).collect::<Vec<_>>());
+
+        // chunks of a few lines each, ending in the middle of a line
+        for chunk_size in [1, 100, 1000] {
+            let options = ReaderOptions::new().workers(3).chunk_size(chunk_size);
+            let (chan, report) = read_file_with(&file.0, options).unwrap();
+            assert_eq!(ids(chan), (1..=count).collect::<Vec<_>>());
+            assert_eq!(report.summary().lines, count);
+        }
+
+        let file = TempFile::new("read_file_empty", b"");
+        assert!(ids(read_file(&file.0).unwrap()).is_empty());
+
+        assert!(read_file("does/not/exist.lsif").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_file_from_pipe() {
+        let count = 100;
+        let path = std::env::temp_dir().join(format!("read_file_pipe-{}", std::process::id()));
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let file = TempFile(path);
+
+        let writer = {
+            let path = file.0.clone();
+            std::thread::spawn(move || std::fs::write(path, lines(count)).unwrap())
+        };
+
+        let chan = read_file(&file.0).unwrap();
+        assert_eq!(ids(chan), (1..=count).collect::<Vec<_>>());
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn read_file_errors_keep_their_place() {
+        let string = format!("{}\nnot json\n{}", lines(20), lines(4));
+        let file = TempFile::new("read_file_errors", string.as_bytes());
+        let options = ReaderOptions::new().chunk_size(64);
+
+        let results: Vec<_> = read_file_with(&file.0, options.clone())
+            .unwrap()
+            .0
+            .iter()
+            .collect();
+        assert_eq!(results.len(), 21);
+
+        let err = results[20].as_ref().err().unwrap();
+        assert_eq!(err.location.line, Some(21));
+
+        // located just like reading the lines one by one would
+        let expected = read_async(Box::new(Cursor::new(string.clone())))
+            .iter()
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(err.location, expected.location);
+
+        let (chan, report) =
+            read_file_with(&file.0, options.error_policy(ErrorPolicy::Collect)).unwrap();
+        assert_eq!(ids(chan).len(), 24);
+        assert_eq!(report.summary().skipped, vec![21]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        use flate2::write::GzEncoder;
+        use futures::stream::StreamExt;
+        use std::io::Write;
+
+        let count = 100;
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(lines(count).as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let file = TempFile::new("read_file_gzip", &compressed);
+        assert_eq!(
+            ids(read_file(&file.0).unwrap()),
+            (1..=count).collect::<Vec<_>>()
+        );
+
+        let chan = read_async(Box::new(Cursor::new(compressed.clone())));
+        assert_eq!(ids(chan), (1..=count).collect::<Vec<_>>());
+
+        let elements: Vec<_> = Reader::new(Cursor::new(compressed.clone()))
+            .map(|element| element.unwrap().id)
+            .collect();
+        assert_eq!(elements, (1..=count).collect::<Vec<_>>());
+
+        let reader = AsyncReader::new(futures::io::Cursor::new(compressed));
+        let elements: Vec<_> = futures::executor::block_on(
+            reader
+                .map(|element| element.unwrap().id)
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(elements, (1..=count).collect::<Vec<_>>());
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn disabled_compression() {
+        use futures::stream::StreamExt;
+
+        let chan = read_async(Box::new(Cursor::new(vec![0x28, 0xb5, 0x2f, 0xfd, 0])));
+        let results: Vec<_> = chan.iter().collect();
+
+        assert_eq!(results.len(), 1);
+        let err = results[0].as_ref().err().unwrap();
+        assert!(matches!(err.kind, ErrorKind::IO(_)));
+        assert_eq!(err.location.line, Some(1));
+
+        let results: Vec<_> = Reader::new(Cursor::new(vec![0x28, 0xb5, 0x2f, 0xfd, 0])).collect();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(
+            results[0].as_ref().err().unwrap().kind,
+            ErrorKind::IO(_)
+        ));
 
-        assert_eq!(count, 7);
+        let reader = AsyncReader::new(futures::io::Cursor::new(vec![0x28, 0xb5, 0x2f, 0xfd, 0]));
+        let results: Vec<_> = futures::executor::block_on(reader.collect::<Vec<_>>());
+        assert_eq!(results.len(), 1);
+        let err = results[0].as_ref().err().unwrap();
+        assert!(matches!(err.kind, ErrorKind::IO(_)));
+        assert!(err.to_string().contains("`zstd` feature"));
     }
 }
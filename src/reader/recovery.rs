@@ -0,0 +1,226 @@
+use super::options::ReaderOptions;
+use super::progress::Tracker;
+use super::types::*;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// What a reader does with lines that fail to deserialize.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Pass the first error on and stop reading.
+    #[default]
+    Abort,
+    /// Skip every line that fails, keeping its error in the summary.
+    Collect,
+    /// Skip up to this many failing lines without passing their errors on, then abort on
+    /// the next one.
+    SkipUpTo(usize),
+}
+
+/// An account of the lines a reader got through.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    /// Lines read, including blank ones and the ones that failed.
+    pub lines: u64,
+    /// Line numbers of the skipped lines.
+    pub skipped: Vec<u64>,
+    /// Errors of the skipped lines. Only kept under `ErrorPolicy::Collect`.
+    pub errors: Vec<ProtocolError>,
+    /// The error reading stopped at, if any.
+    pub aborted: Option<ProtocolError>,
+}
+
+impl Summary {
+    /// Whether every line was read without error.
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty() && self.aborted.is_none()
+    }
+}
+
+/// Formats as e.g. `read 120 lines, skipped 2 (lines 4, 17)`.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read {} lines", self.lines)?;
+
+        if !self.skipped.is_empty() {
+            let lines: Vec<String> = self.skipped.iter().map(u64::to_string).collect();
+            write!(
+                f,
+                ", skipped {} (lines {})",
+                self.skipped.len(),
+                lines.join(", ")
+            )?;
+        }
+
+        if let Some(err) = &self.aborted {
+            write!(f, ", aborted at {}", err)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A handle on the summary of a running reader. The summary is final once the reader's
+/// output has been drained.
+#[derive(Clone, Default)]
+pub struct Report(Arc<Mutex<Summary>>);
+
+impl Report {
+    pub fn summary(&self) -> Summary {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Applies an `ErrorPolicy` to the results of a reader in input order, tracking progress
+/// along the way.
+pub(crate) struct Recovery {
+    policy: ErrorPolicy,
+    report: Report,
+    tracker: Tracker,
+}
+
+impl Recovery {
+    pub(crate) fn new(options: &ReaderOptions) -> Recovery {
+        Recovery {
+            policy: options.policy,
+            report: Report::default(),
+            tracker: Tracker::new(options.observer.clone(), options.progress_interval),
+        }
+    }
+
+    pub(crate) fn report(&self) -> Report {
+        self.report.clone()
+    }
+
+    /// Returns what to pass on for the result of a line of `bytes` bytes, if anything.
+    /// Nothing more should be read once an error is passed on.
+    pub(crate) fn apply(&mut self, result: Result<Element>, bytes: u64) -> Option<Result<Element>> {
+        self.tracker.record(bytes, result.is_err());
+
+        let mut summary = self.report.0.lock().unwrap();
+        summary.lines += 1;
+
+        let err = match result {
+            Ok(element) => return Some(Ok(element)),
+            Err(err) => err,
+        };
+
+        // a dump that can't be read any further has no lines left to skip to
+        let skip = match self.policy {
+            _ if matches!(err.kind, ErrorKind::IO(_)) => false,
+            ErrorPolicy::Abort => false,
+            ErrorPolicy::Collect => true,
+            ErrorPolicy::SkipUpTo(max) => summary.skipped.len() < max,
+        };
+
+        if !skip {
+            log::error!("stopped reading at {}", err);
+            summary.aborted = Some(err.clone());
+            return Some(Err(err));
+        }
+
+        match self.policy {
+            ErrorPolicy::SkipUpTo(_) => log::debug!("skipped {}", err),
+            _ => log::warn!("skipped {}", err),
+        }
+
+        summary.skipped.push(err.location.line.unwrap_or_default());
+        if self.policy == ErrorPolicy::Collect {
+            summary.errors.push(err);
+        }
+        None
+    }
+
+    /// Counts a blank line of `bytes` bytes, which has nothing to pass on.
+    pub(crate) fn skip_blank(&mut self, bytes: u64) {
+        self.tracker.skip_blank(bytes);
+        self.report.0.lock().unwrap().lines += 1;
+    }
+
+    /// Sends the last progress report, once the reader has stopped for whatever reason.
+    pub(crate) fn finish(&mut self) {
+        self.tracker.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Vec<Result<Element>> {
+        (1..=6)
+            .map(|line| {
+                if line % 2 == 0 {
+                    Err(ProtocolError::new(ErrorKind::Other("bad line".into())).at_line(line, 0))
+                } else {
+                    Ok(Element {
+                        id: line,
+                        kind: ElementKind::Vertex(VertexLabel::ResultSet),
+                        payload: None,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the results through the policy like a reader would, returning whether each
+    /// passed on item was an element.
+    fn run(policy: ErrorPolicy) -> (Vec<bool>, Summary) {
+        let mut recovery = Recovery::new(&ReaderOptions::new().error_policy(policy));
+        let mut passed = Vec::new();
+
+        for result in results() {
+            match recovery.apply(result, 0) {
+                Some(Ok(_)) => passed.push(true),
+                Some(Err(_)) => {
+                    passed.push(false);
+                    break;
+                }
+                None => {}
+            }
+        }
+
+        (passed, recovery.report().summary())
+    }
+
+    #[test]
+    fn abort() {
+        let (passed, summary) = run(ErrorPolicy::Abort);
+
+        assert_eq!(passed, vec![true, false]);
+        assert_eq!(summary.lines, 2);
+        assert_eq!(summary.aborted.unwrap().location.line, Some(2));
+    }
+
+    #[test]
+    fn collect() {
+        let (passed, summary) = run(ErrorPolicy::Collect);
+
+        assert_eq!(passed, vec![true, true, true]);
+        assert_eq!(summary.skipped, vec![2, 4, 6]);
+        assert_eq!(summary.errors.len(), 3);
+        assert!(summary.aborted.is_none());
+        assert_eq!(
+            summary.to_string(),
+            "read 6 lines, skipped 3 (lines 2, 4, 6)"
+        );
+    }
+
+    #[test]
+    fn skip_up_to() {
+        let (passed, summary) = run(ErrorPolicy::SkipUpTo(1));
+
+        assert_eq!(passed, vec![true, true, false]);
+        assert_eq!(summary.skipped, vec![2]);
+        assert!(summary.errors.is_empty());
+        assert!(!summary.is_clean());
+        assert_eq!(summary.aborted.unwrap().location.line, Some(4));
+
+        let (passed, summary) = run(ErrorPolicy::SkipUpTo(3));
+
+        assert_eq!(passed, vec![true, true, true]);
+        assert_eq!(summary.skipped.len(), 3);
+        assert!(summary.aborted.is_none());
+    }
+}
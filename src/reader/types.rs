@@ -1,59 +1,205 @@
-use lsp_types::Url;
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag, DocumentLink, DocumentSymbol,
+    FoldingRange, HoverContents, MonikerKind, NumberOrString, Range as LSRange, SymbolKind,
+    UniquenessLevel, Url,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use thiserror::Error;
 
+use std::any::Any;
 use std::fmt::Display;
 
 use std::num::ParseIntError;
 use std::result;
+use std::str::Utf8Error;
+use std::sync::Arc;
 
 use serde_json::Error;
 
 pub type Result<T> = result::Result<T, ProtocolError>;
 
+/// An error together with where in the dump it happened.
+#[derive(Error, Clone, Debug)]
+pub struct ProtocolError {
+    pub kind: ErrorKind,
+    pub location: Location,
+}
+
 #[derive(Error, Clone, Debug)]
-pub enum ProtocolError {
-    IDParse(#[from] ParseIntError),
+pub enum ErrorKind {
+    #[error("invalid id: {0}")]
+    IDParse(String),
+    #[error("{0}")]
     JSONParse(String),
+    #[error("unsupported LSIF version {0}")]
+    UnsupportedVersion(String),
+    #[error("failed to read the dump: {0}")]
+    IO(String),
+    #[error("{0}")]
     Other(String),
 }
 
+/// Where an error happened. Each part is only known once reading has progressed far
+/// enough: errors from `deserialize_element` have no line number, and errors in lines
+/// that aren't valid JSON have no element id or label.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    /// The 1-based line number within the dump.
+    pub line: Option<u64>,
+    /// The offset in bytes of the error from the start of the dump, or from the start of
+    /// the line for errors without a line number.
+    pub offset: Option<u64>,
+    /// The id of the element as it appears in the input.
+    pub id: Option<String>,
+    pub label: Option<String>,
+}
+
+impl ProtocolError {
+    pub fn new(kind: ErrorKind) -> ProtocolError {
+        ProtocolError {
+            kind,
+            location: Location::default(),
+        }
+    }
+
+    /// Places an error from deserializing a single line at the given line number, whose
+    /// first byte is at `line_offset` in the dump.
+    pub fn at_line(mut self, line: u64, line_offset: u64) -> ProtocolError {
+        self.location.line = Some(line);
+        self.location.offset = Some(line_offset + self.location.offset.unwrap_or_default());
+        self
+    }
+
+    /// Sets the offset of the error within its line, unless a more precise one is known.
+    pub(crate) fn at_offset(mut self, offset: usize) -> ProtocolError {
+        self.location.offset.get_or_insert(offset as u64);
+        self
+    }
+
+    pub(crate) fn in_element(mut self, id: Option<&str>, label: &str) -> ProtocolError {
+        self.location.id = id.map(String::from);
+        self.location.label = Some(label.into());
+        self
+    }
+}
+
+/// Formats as e.g. `line 3, byte 120, element 5 (range): missing field `start``.
 impl Display for ProtocolError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{:?}", self))
+        let Location {
+            line,
+            offset,
+            id,
+            label,
+        } = &self.location;
+
+        let mut parts = Vec::new();
+        if let Some(line) = line {
+            parts.push(format!("line {}", line));
+        }
+        if let Some(offset) = offset {
+            parts.push(format!("byte {}", offset));
+        }
+        match (id, label) {
+            (Some(id), Some(label)) => parts.push(format!("element {} ({})", id, label)),
+            (Some(id), None) => parts.push(format!("element {}", id)),
+            (None, Some(label)) => parts.push(format!("element ({})", label)),
+            (None, None) => {}
+        }
+
+        if parts.is_empty() {
+            write!(f, "{}", self.kind)
+        } else {
+            write!(f, "{}: {}", parts.join(", "), self.kind)
+        }
+    }
+}
+
+impl From<ErrorKind> for ProtocolError {
+    fn from(kind: ErrorKind) -> Self {
+        ProtocolError::new(kind)
+    }
+}
+
+impl From<ParseIntError> for ProtocolError {
+    fn from(e: ParseIntError) -> Self {
+        ErrorKind::IDParse(e.to_string()).into()
+    }
+}
+
+impl From<Utf8Error> for ProtocolError {
+    fn from(e: Utf8Error) -> Self {
+        ErrorKind::IDParse(e.to_string()).into()
     }
 }
 
+/// The position serde_json appends to its messages is dropped: it is relative to whatever
+/// part of the line was being parsed, and the location says where the error is instead.
 impl From<Error> for ProtocolError {
     fn from(e: Error) -> Self {
-        ProtocolError::JSONParse(format!("{}", e))
+        let message = e.to_string();
+        let position = format!(" at line {} column {}", e.line(), e.column());
+        let message = message.strip_suffix(&position).unwrap_or(&message);
+        ErrorKind::JSONParse(message.into()).into()
     }
 }
 
 impl From<anyhow::Error> for ProtocolError {
     fn from(e: anyhow::Error) -> Self {
-        ProtocolError::Other(format!("{}", e))
+        ErrorKind::Other(format!("{}", e)).into()
     }
 }
 
+pub use crate::types::{EdgeLabel, VertexLabel};
+
 #[derive(Clone)]
 pub struct Element {
     pub id: u64,
-    pub el_type: String,
-    pub label: String,
+    pub kind: ElementKind,
     pub payload: Option<Payload>,
 }
 
+/// The `type` of an element together with its `label`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ElementKind {
+    Vertex(VertexLabel),
+    Edge(EdgeLabel),
+}
+
+impl ElementKind {
+    pub fn label(&self) -> &str {
+        match self {
+            ElementKind::Vertex(label) => label.as_str(),
+            ElementKind::Edge(label) => label.as_str(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum Payload {
     Edge(Edge),
     MetaData(MetaData),
     Range(Range),
     Document(Url),
+    Hover(HoverContents),
     ResultSet(ResultSet),
+    DefinitionResult,
+    DeclarationResult,
+    TypeDefinitionResult,
+    ReferenceResult,
+    ImplementationResult,
     Moniker(Moniker),
     PackageInformation(PackageInformation),
     Diagnostics(Vec<Diagnostic>),
+    DocumentSymbols(DocumentSymbols),
+    FoldingRanges(Vec<FoldingRange>),
+    DocumentLinks(Vec<DocumentLink>),
+    Project(Project),
+    Event(Event),
+    /// Produced by a deserializer registered with a `Registry`.
+    Custom(Arc<dyn Any + Send + Sync>),
 }
 
 #[derive(Clone)]
@@ -62,12 +208,73 @@ pub struct Edge {
     pub in_v: u64,
     pub in_vs: Vec<u64>,
     pub document: u64,
+    pub property: Option<ItemProperty>,
+}
+
+/// The `property` of an item edge, distinguishing which part of a result
+/// the edge's in-vertices contribute to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ItemProperty {
+    Definitions,
+    Declarations,
+    References,
+    ReferenceResults,
+    ReferenceLinks,
+    /// Any other property, so the edge isn't rejected over it.
+    Unknown(String),
+}
+
+impl ItemProperty {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ItemProperty::Definitions => "definitions",
+            ItemProperty::Declarations => "declarations",
+            ItemProperty::References => "references",
+            ItemProperty::ReferenceResults => "referenceResults",
+            ItemProperty::ReferenceLinks => "referenceLinks",
+            ItemProperty::Unknown(property) => property,
+        }
+    }
+}
+
+impl From<&str> for ItemProperty {
+    fn from(property: &str) -> Self {
+        match property {
+            "definitions" => ItemProperty::Definitions,
+            "declarations" => ItemProperty::Declarations,
+            "references" => ItemProperty::References,
+            "referenceResults" => ItemProperty::ReferenceResults,
+            "referenceLinks" => ItemProperty::ReferenceLinks,
+            other => ItemProperty::Unknown(other.into()),
+        }
+    }
+}
+
+impl Serialize for ItemProperty {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ItemProperty {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
 }
 
 #[derive(Clone)]
 pub struct MetaData {
     pub version: String,
     pub project_root: String,
+    pub position_encoding: String,
+    pub tool_info: Option<ToolInfo>,
+}
+
+#[derive(Clone)]
+pub struct ToolInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub args: Vec<String>,
 }
 
 #[derive(Clone)]
@@ -76,32 +283,185 @@ pub struct Range {
     pub start_character: u32,
     pub end_line: u32,
     pub end_character: u32,
+    pub tag: Option<RangeTag>,
+}
+
+/// Describes the symbol a range vertex covers, as emitted by indexers that
+/// support document symbols and workspace symbol search.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RangeTag {
+    Declaration(DeclarationTag),
+    Definition(DeclarationTag),
+    Reference { text: String },
+    Unknown { text: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DeclarationTag {
+    pub text: String,
+    pub kind: SymbolKind,
+    #[serde(rename = "fullRange")]
+    pub full_range: LSRange,
+    #[serde(default)]
+    pub deprecated: bool,
+    pub detail: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Project {
+    pub kind: String,
+    pub name: String,
+    pub resource: Option<Url>,
+    pub contents: Option<String>,
+}
+
+/// A `$event` vertex marking the start or end of a document or project scope.
+/// `data` is the id of the document or project vertex the scope belongs to.
+#[derive(Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub scope: EventScope,
+    pub data: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Begin,
+    End,
+    Unknown(String),
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            EventKind::Begin => "begin",
+            EventKind::End => "end",
+            EventKind::Unknown(kind) => kind,
+        }
+    }
+}
+
+impl From<&str> for EventKind {
+    fn from(kind: &str) -> Self {
+        match kind {
+            "begin" => EventKind::Begin,
+            "end" => EventKind::End,
+            other => EventKind::Unknown(other.into()),
+        }
+    }
+}
+
+impl Serialize for EventKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for EventKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventScope {
+    Document,
+    Project,
+    /// E.g. the `monikerAttach` and `group` scopes of newer indexers.
+    Unknown(String),
+}
+
+impl EventScope {
+    pub fn as_str(&self) -> &str {
+        match self {
+            EventScope::Document => "document",
+            EventScope::Project => "project",
+            EventScope::Unknown(scope) => scope,
+        }
+    }
+}
+
+impl From<&str> for EventScope {
+    fn from(scope: &str) -> Self {
+        match scope {
+            "document" => EventScope::Document,
+            "project" => EventScope::Project,
+            other => EventScope::Unknown(other.into()),
+        }
+    }
+}
+
+impl Serialize for EventScope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for EventScope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
 }
 
 #[derive(Clone)]
 pub struct ResultSet {}
 
+/// The result of a documentSymbolResult vertex. Indexers either inline full
+/// LSP document symbols, or reference range vertices (whose tags describe
+/// the symbol) by id.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DocumentSymbols {
+    Symbols(Vec<DocumentSymbol>),
+    RangeBased(Vec<RangeBasedDocumentSymbol>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeBasedDocumentSymbol {
+    pub id: u64,
+    pub children: Vec<RangeBasedDocumentSymbol>,
+}
+
 #[derive(Clone)]
 pub struct Moniker {
-    pub kind: String,
+    /// Optional since protocol version 0.5, where `unique` carries most of its meaning.
+    pub kind: Option<MonikerKind>,
     pub scheme: String,
     pub identifier: String,
+    /// Absent for dumps predating protocol version 0.5.
+    pub unique: Option<UniquenessLevel>,
 }
 
 #[derive(Clone)]
 pub struct PackageInformation {
     pub name: String,
-    pub version: String,
+    pub manager: String,
+    pub version: Option<String>,
+    pub uri: Option<Url>,
+    pub contents: Option<String>,
+    pub repository: Option<Repository>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Repository {
+    #[serde(rename = "type")]
+    pub repo_type: String,
+    pub url: String,
+    #[serde(rename = "commitId")]
+    pub commit_id: Option<String>,
+    pub directory: Option<String>,
 }
 
 #[derive(Clone)]
 pub struct Diagnostic {
-    pub severity: u64,
-    pub code: String,
+    pub severity: Option<DiagnosticSeverity>,
+    pub code: Option<NumberOrString>,
     pub message: String,
     pub source: String,
     pub start_line: u64,
     pub start_character: u64,
     pub end_line: u64,
     pub end_character: u64,
+    pub related_information: Vec<DiagnosticRelatedInformation>,
+    pub tags: Vec<DiagnosticTag>,
 }
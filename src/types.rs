@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use lsp_types::MarkedString;
 use lsp_types::Position;
@@ -23,9 +23,10 @@ pub struct Vertex {
     pub label: VertexLabel,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum VertexLabel {
     Metadata,
+    Event,
     Project,
     Range,
     Location,
@@ -35,6 +36,7 @@ pub enum VertexLabel {
     ResultSet,
     DocumentSymbolResult,
     FoldingRangeResult,
+    DocumentLinkResult,
     DiagnosticResult,
     DeclarationResult,
     DefinitionResult,
@@ -42,6 +44,74 @@ pub enum VertexLabel {
     HoverResult,
     ReferenceResult,
     ImplementationResult,
+    /// E.g. a vertex emitted by an indexer-specific extension.
+    Unknown(String),
+}
+
+impl VertexLabel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            VertexLabel::Metadata => "metaData",
+            VertexLabel::Event => "$event",
+            VertexLabel::Project => "project",
+            VertexLabel::Range => "range",
+            VertexLabel::Location => "location",
+            VertexLabel::Document => "document",
+            VertexLabel::Moniker => "moniker",
+            VertexLabel::PackageInfo => "packageInformation",
+            VertexLabel::ResultSet => "resultSet",
+            VertexLabel::DocumentSymbolResult => "documentSymbolResult",
+            VertexLabel::FoldingRangeResult => "foldingRangeResult",
+            VertexLabel::DocumentLinkResult => "documentLinkResult",
+            VertexLabel::DiagnosticResult => "diagnosticResult",
+            VertexLabel::DeclarationResult => "declarationResult",
+            VertexLabel::DefinitionResult => "definitionResult",
+            VertexLabel::TypeDefinitionResult => "typeDefinitionResult",
+            VertexLabel::HoverResult => "hoverResult",
+            VertexLabel::ReferenceResult => "referenceResult",
+            VertexLabel::ImplementationResult => "implementationResult",
+            VertexLabel::Unknown(label) => label,
+        }
+    }
+}
+
+impl From<&str> for VertexLabel {
+    fn from(label: &str) -> Self {
+        match label {
+            "metaData" => VertexLabel::Metadata,
+            "$event" => VertexLabel::Event,
+            "project" => VertexLabel::Project,
+            "range" => VertexLabel::Range,
+            "location" => VertexLabel::Location,
+            "document" => VertexLabel::Document,
+            "moniker" => VertexLabel::Moniker,
+            "packageInformation" => VertexLabel::PackageInfo,
+            "resultSet" => VertexLabel::ResultSet,
+            "documentSymbolResult" => VertexLabel::DocumentSymbolResult,
+            "foldingRangeResult" => VertexLabel::FoldingRangeResult,
+            "documentLinkResult" => VertexLabel::DocumentLinkResult,
+            "diagnosticResult" => VertexLabel::DiagnosticResult,
+            "declarationResult" => VertexLabel::DeclarationResult,
+            "definitionResult" => VertexLabel::DefinitionResult,
+            "typeDefinitionResult" => VertexLabel::TypeDefinitionResult,
+            "hoverResult" => VertexLabel::HoverResult,
+            "referenceResult" => VertexLabel::ReferenceResult,
+            "implementationResult" => VertexLabel::ImplementationResult,
+            other => VertexLabel::Unknown(other.into()),
+        }
+    }
+}
+
+impl Serialize for VertexLabel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for VertexLabel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,7 +121,7 @@ pub struct Edge {
     pub label: EdgeLabel,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum EdgeLabel {
     Contains,
     Item,
@@ -69,6 +139,68 @@ pub enum EdgeLabel {
     TextDocHover,
     TextDocReferences,
     TextDocImplementation,
+    /// Kept as written, so a `Registry` can be keyed by it.
+    Unknown(String),
+}
+
+impl EdgeLabel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            EdgeLabel::Contains => "contains",
+            EdgeLabel::Item => "item",
+            EdgeLabel::Next => "next",
+            EdgeLabel::Moniker => "moniker",
+            EdgeLabel::NextMoniker => "nextMoniker",
+            EdgeLabel::PackageInfo => "packageInformation",
+            EdgeLabel::TextDocDocumentSymbol => "textDocument/documentSymbol",
+            EdgeLabel::TextDocFoldingRange => "textDocument/foldingRange",
+            EdgeLabel::TextDocDocumentLink => "textDocument/documentLink",
+            EdgeLabel::TextDocDiagnostic => "textDocument/diagnostic",
+            EdgeLabel::TextDocDefinition => "textDocument/definition",
+            EdgeLabel::TextDocDeclaration => "textDocument/declaration",
+            EdgeLabel::TextDocTypeDefinition => "textDocument/typeDefinition",
+            EdgeLabel::TextDocHover => "textDocument/hover",
+            EdgeLabel::TextDocReferences => "textDocument/references",
+            EdgeLabel::TextDocImplementation => "textDocument/implementation",
+            EdgeLabel::Unknown(label) => label,
+        }
+    }
+}
+
+impl From<&str> for EdgeLabel {
+    fn from(label: &str) -> Self {
+        match label {
+            "contains" => EdgeLabel::Contains,
+            "item" => EdgeLabel::Item,
+            "next" => EdgeLabel::Next,
+            "moniker" => EdgeLabel::Moniker,
+            "nextMoniker" => EdgeLabel::NextMoniker,
+            "packageInformation" => EdgeLabel::PackageInfo,
+            "textDocument/documentSymbol" => EdgeLabel::TextDocDocumentSymbol,
+            "textDocument/foldingRange" => EdgeLabel::TextDocFoldingRange,
+            "textDocument/documentLink" => EdgeLabel::TextDocDocumentLink,
+            "textDocument/diagnostic" => EdgeLabel::TextDocDiagnostic,
+            "textDocument/definition" => EdgeLabel::TextDocDefinition,
+            "textDocument/declaration" => EdgeLabel::TextDocDeclaration,
+            "textDocument/typeDefinition" => EdgeLabel::TextDocTypeDefinition,
+            "textDocument/hover" => EdgeLabel::TextDocHover,
+            "textDocument/references" => EdgeLabel::TextDocReferences,
+            "textDocument/implementation" => EdgeLabel::TextDocImplementation,
+            other => EdgeLabel::Unknown(other.into()),
+        }
+    }
+}
+
+impl Serialize for EdgeLabel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for EdgeLabel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -547,3 +679,42 @@ impl TextDocumentReferences {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn labels_serialize_as_in_the_spec() {
+        let vertex = Vertex {
+            el: Element {
+                id: 1,
+                el_type: ElementType::Vertex,
+            },
+            label: VertexLabel::Metadata,
+        };
+        assert_eq!(
+            serde_json::to_value(&vertex).unwrap()["label"],
+            json!("metaData")
+        );
+
+        let edge = Edge {
+            el: Element {
+                id: 2,
+                el_type: ElementType::Edge,
+            },
+            label: EdgeLabel::TextDocHover,
+        };
+        assert_eq!(
+            serde_json::to_value(&edge).unwrap()["label"],
+            json!("textDocument/hover")
+        );
+
+        for label in ["resultSet", "vendorThing"] {
+            let parsed: VertexLabel = serde_json::from_value(json!(label)).unwrap();
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), json!(label));
+        }
+    }
+}